@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use regex::engine::do_matching;
+use regex::engine::{do_matching, jit::JitRegex, Cache, Engine, Regex};
 use std::time::Duration;
 
 const REDOS_REGEX: &[(&str, &str, &str)] = &[
@@ -48,5 +48,60 @@ fn depth_first(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, width_first, depth_first);
+/// Matches precompiled expressions, comparing a fresh cache per match with a
+/// cache reused across matches, which allocates nothing once warm.
+fn cached(c: &mut Criterion) {
+    for (name, engine) in [
+        ("Width First", Engine::BreadthFirst),
+        ("Depth First", Engine::DepthFirst),
+    ] {
+        for (label, is_reused) in [("fresh cache", false), ("reused cache", true)] {
+            let mut g = c.benchmark_group(format!("{name} ({label})"));
+            g.measurement_time(Duration::from_secs(5));
+
+            for i in REDOS_REGEX {
+                let regex = Regex::new(i.1).unwrap();
+                let mut cache = Cache::new();
+                g.bench_with_input(i.0, &i.2, |b, line| {
+                    if is_reused {
+                        b.iter(|| regex.is_match_engine(line, engine, &mut cache))
+                    } else {
+                        b.iter(|| regex.is_match_engine(line, engine, &mut Cache::new()))
+                    }
+                });
+            }
+        }
+    }
+}
+
+/// Matches the expressions short enough for the bit-parallel evaluator.
+fn bit_parallel(c: &mut Criterion) {
+    let mut g = c.benchmark_group("Bit Parallel");
+    g.measurement_time(Duration::from_secs(5));
+
+    for i in REDOS_REGEX {
+        let regex = Regex::new(i.1).unwrap();
+        if regex.auto_engine() != Engine::BitParallel {
+            continue;
+        }
+        let mut cache = Cache::new();
+        g.bench_with_input(i.0, &i.2, |b, line| {
+            b.iter(|| regex.is_match_engine(line, Engine::BitParallel, &mut cache))
+        });
+    }
+}
+
+/// Matches with native code, or with the depth-first evaluator for the
+/// expressions whose DFA is too large.
+fn jit(c: &mut Criterion) {
+    let mut g = c.benchmark_group("JIT");
+    g.measurement_time(Duration::from_secs(5));
+
+    for i in REDOS_REGEX {
+        let regex = JitRegex::new(&Regex::new(i.1).unwrap());
+        g.bench_with_input(i.0, &i.2, |b, line| b.iter(|| regex.is_match(line)));
+    }
+}
+
+criterion_group!(benches, width_first, depth_first, cached, bit_parallel, jit);
 criterion_main!(benches);
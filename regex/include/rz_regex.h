@@ -0,0 +1,67 @@
+/*
+ * C interface of the regex engine.
+ *
+ * Link with the `regex` cdylib or staticlib. Handles are allocated by the
+ * library and released with their `_free` function, which accepts NULL.
+ * Subjects are passed as a pointer and a length in bytes and must be valid
+ * UTF-8; all offsets are byte offsets into them. Functions taking
+ * `rz_error **error` store a new error there on failure unless it is NULL.
+ * A compiled expression may be shared between threads.
+ */
+#ifndef RZ_REGEX_H
+#define RZ_REGEX_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct rz_regex rz_regex;
+typedef struct rz_captures rz_captures;
+typedef struct rz_error rz_error;
+
+/* Compiles a NUL-terminated pattern. Returns NULL on failure. */
+rz_regex *rz_regex_new(const char *pattern, rz_error **error);
+void rz_regex_free(rz_regex *regex);
+
+/* Returns 1 if the subject matches somewhere, 0 if not and -1 on failure. */
+int rz_regex_is_match(const rz_regex *regex, const char *subject, size_t len,
+                      rz_error **error);
+
+/*
+ * Stores the range of the leftmost match into start and end, which may be
+ * NULL. Returns 1 if there is a match, 0 if not and -1 on failure.
+ */
+int rz_regex_find(const rz_regex *regex, const char *subject, size_t len,
+                  size_t *start, size_t *end, rz_error **error);
+
+/*
+ * Stores the groups of the leftmost match into *captures, to be released with
+ * rz_captures_free. Returns 1 if there is a match, 0 if not and -1 on
+ * failure; *captures is only written when 1 is returned.
+ */
+int rz_regex_captures(const rz_regex *regex, const char *subject, size_t len,
+                      rz_captures **captures, rz_error **error);
+
+/* Number of groups, including group 0 for the whole match. */
+size_t rz_captures_len(const rz_captures *captures);
+
+/*
+ * Stores the range of a group into start and end, which may be NULL.
+ * Returns 1 if the group participated in the match, 0 if not or if the index
+ * is out of range.
+ */
+int rz_captures_get(const rz_captures *captures, size_t index, size_t *start,
+                    size_t *end);
+void rz_captures_free(rz_captures *captures);
+
+/* Message of an error, valid until the error is freed. */
+const char *rz_error_message(const rz_error *error);
+void rz_error_free(rz_error *error);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,85 @@
+#!/usr/bin/env perl
+# Generates src/engine/unicode/tables.rs from the Unicode data shipped with
+# Perl. Run from the crate root:
+#
+#     perl scripts/unicode_tables.pl > src/engine/unicode/tables.rs
+use strict;
+use warnings;
+use Unicode::UCD qw(prop_invlist prop_values prop_value_aliases);
+
+my $version = Unicode::UCD::UnicodeVersion();
+my ($major, $minor, $update) = split /\./, $version;
+
+# Turns an inversion list into (lo, hi) ranges without surrogates.
+sub ranges {
+    my @invlist = @_;
+    push @invlist, 0x110000 if @invlist % 2;
+    my @ranges;
+    for (my $i = 0; $i < @invlist; $i += 2) {
+        my ($lo, $hi) = ($invlist[$i], $invlist[$i + 1] - 1);
+        if ($lo < 0xD800 && $hi >= 0xD800) {
+            push @ranges, [$lo, 0xD7FF] if $lo <= 0xD7FF;
+            $lo = 0xE000;
+        }
+        $lo = 0xE000 if $lo >= 0xD800 && $lo <= 0xDFFF;
+        $hi = 0xD7FF if $hi >= 0xD800 && $hi <= 0xDFFF;
+        push @ranges, [$lo, $hi] if $lo <= $hi;
+    }
+    return @ranges;
+}
+
+sub table {
+    my ($name, @ranges) = @_;
+    my $out = "const $name: Ranges = &[\n";
+    for (my $i = 0; $i < @ranges; $i += 4) {
+        my @line = grep { defined } @ranges[$i .. $i + 3];
+        $out .= "    " . join(" ", map { sprintf("('\\u{%X}', '\\u{%X}'),", @$_) } @line) . "\n";
+    }
+    return $out . "];\n\n";
+}
+
+print <<"HEADER";
+//! Generated by `scripts/unicode_tables.pl` from the Unicode $version data.
+//! Do not edit by hand.
+
+use super::{GraphemeBreak, Ranges};
+
+pub const UNICODE_VERSION: (u32, u32, u32) = ($major, $minor, $update);
+
+HEADER
+
+for my $prop (["General_Category", "GENERAL_CATEGORY", "GC"], ["Script", "SCRIPT", "SC"]) {
+    my ($long_prop, $const, $prefix) = @$prop;
+    my @values = sort { $a->[0] cmp $b->[0] }
+        grep { defined $_->[1] }
+        grep { $long_prop ne "General_Category" || length($_->[0]) == 2 }
+        map { [ (prop_value_aliases($long_prop, $_))[0, 1] ] } prop_values($long_prop);
+    print "/// (short name, long name, ranges) of each value\n";
+    print "pub const $const: &[(&str, &str, Ranges)] = &[\n";
+    for my $v (@values) {
+        print "    (\"$v->[0]\", \"$v->[1]\", ${prefix}_\U$v->[0]\E),\n";
+    }
+    print "];\n\n";
+    for my $v (@values) {
+        print table("${prefix}_\U$v->[0]", ranges(prop_invlist("$long_prop=$v->[0]")));
+    }
+}
+
+my @breaks = (
+    ["CR", "Cr"], ["LF", "Lf"], ["Control", "Control"], ["Extend", "Extend"],
+    ["ZWJ", "Zwj"], ["RI", "RegionalIndicator"], ["Prepend", "Prepend"],
+    ["SpacingMark", "SpacingMark"], ["L", "L"], ["V", "V"], ["T", "T"],
+    ["LV", "Lv"], ["LVT", "Lvt"],
+);
+my @gcb;
+for my $b (@breaks) {
+    push @gcb, map { [@$_, $b->[1]] } ranges(prop_invlist("GCB=$b->[0]"));
+}
+print "/// grapheme cluster break property of the chars other than `Other`\n";
+print "pub const GRAPHEME_BREAK: &[(char, char, GraphemeBreak)] = &[\n";
+for my $r (sort { $a->[0] <=> $b->[0] } @gcb) {
+    printf "    ('\\u{%X}', '\\u{%X}', GraphemeBreak::%s),\n", @$r;
+}
+print "];\n\n";
+
+print "pub ", table("EXTENDED_PICTOGRAPHIC", ranges(prop_invlist("Extended_Pictographic")));
@@ -0,0 +1,283 @@
+//! Interactive playground. Enter a pattern with `:p`, then test lines; every
+//! test line is matched again whenever the pattern changes. While a pattern
+//! is typed after `:p` or edited with `:e`, a hint after the cursor shows how
+//! many test lines it matches, updated on every key.
+//!
+//! Built only with the `playground` feature, which brings in `rustyline`.
+//!
+//! ```text
+//! $ cargo run -p regex --features playground --bin playground -- 'a(b|c)+'
+//! regex> xabcbx
+//! match   xabcbx
+//!          ^^^^  0
+//!             ^  1
+//!                depth-first breadth-first
+//! instructions            25            29
+//! threads                 29            29
+//! pruned                   0             0
+//! peak queue               7             3
+//! starts                   2             2
+//! ```
+//!
+//! With `--explain PATTERN`, prints the description of the pattern from
+//! [`explain`] and exits, for pasting into pattern reviews. Put
+//! `--syntax basic` or `--syntax extended` before the pattern to describe a
+//! POSIX pattern.
+
+use regex::engine::{explain::explain, MatchStats, Regex, RegexBuilder, RegexError, Syntax};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::FileHistory, validate::Validator, Context, Editor, Helper,
+};
+use std::{env, process::ExitCode};
+
+type DynError = Box<dyn std::error::Error + 'static>;
+
+/// reads a counter of the statistics
+type Field = fn(&MatchStats) -> usize;
+
+const HELP: &str = "\
+:p PATTERN  set the pattern and match the test lines again
+:e          edit the current pattern
+:code       show the compiled program
+:explain    describe the pattern in English
+:lines      match the test lines again
+:clear      forget the test lines
+:help       show this help
+:q          quit
+anything else is a test line";
+
+struct Playground {
+    pattern: String,
+    regex: Option<Regex>,
+    lines: Vec<String>,
+}
+
+impl Playground {
+    fn set_pattern(&mut self, pattern: &str) {
+        self.pattern = pattern.to_string();
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                self.regex = Some(regex);
+                self.run_all();
+            }
+            Err(RegexError::Parse(e)) => {
+                self.regex = None;
+                print!("{}", e.diagnostic());
+            }
+            Err(e) => {
+                self.regex = None;
+                println!("error: {e}");
+            }
+        }
+    }
+
+    fn run_all(&self) {
+        for line in self.lines.iter() {
+            self.run(line);
+        }
+    }
+
+    fn run(&self, line: &str) {
+        let regex = match &self.regex {
+            Some(regex) => regex,
+            None => {
+                println!("no pattern, set one with :p PATTERN");
+                return;
+            }
+        };
+
+        match regex.captures(line, true) {
+            Ok(Some(groups)) => {
+                println!("match   {line}");
+                for (n, group) in groups.iter().enumerate() {
+                    match group {
+                        Some(range) => println!("        {}  {n}", underline(line, range)),
+                        None => println!("        (unset)  {n}"),
+                    }
+                }
+            }
+            Ok(None) => println!("no match {line}"),
+            Err(e) => println!("error   {e}"),
+        }
+
+        let stats = [true, false].map(|is_depth| regex.captures_with_stats(line, is_depth));
+        let rows: [(&str, Field); 5] = [
+            ("instructions", |s| s.instructions),
+            ("threads", |s| s.threads_created),
+            ("pruned", |s| s.threads_pruned),
+            ("peak queue", |s| s.peak_queue_len),
+            ("starts", |s| s.start_positions),
+        ];
+        println!("{:<14}{:>12}{:>14}", "", "depth-first", "breadth-first");
+        for (name, field) in rows {
+            let cell = |res: &Result<_, _>| match res {
+                Ok((_, stats)) => field(stats).to_string(),
+                Err(_) => "unsupported".to_string(),
+            };
+            println!("{name:<14}{:>12}{:>14}", cell(&stats[0]), cell(&stats[1]));
+        }
+    }
+
+    fn print_code(&self) {
+        match &self.regex {
+            Some(regex) => {
+                for (pc, inst) in regex.code().iter().enumerate() {
+                    println!("{pc:>4}: {inst}");
+                }
+            }
+            None => println!("no pattern, set one with :p PATTERN"),
+        }
+    }
+
+    fn print_explanation(&self) {
+        match explain(&self.pattern) {
+            Ok(text) => print!("{text}"),
+            Err(e) => print!("{}", e.diagnostic()),
+        }
+    }
+}
+
+/// Hints the number of test lines matched by the pattern being typed.
+struct LiveHint {
+    lines: Vec<String>,
+    /// the whole input is the pattern, as with `:e`, rather than `:p PATTERN`
+    is_pattern: bool,
+}
+
+impl Hinter for LiveHint {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<String> {
+        let pattern = if self.is_pattern {
+            line
+        } else {
+            line.strip_prefix(":p ")?
+        };
+        if pos < line.len() || pattern.is_empty() || self.lines.is_empty() {
+            return None;
+        }
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(_) => return Some("  (invalid)".to_string()),
+        };
+        let matched = self
+            .lines
+            .iter()
+            .filter(|line| regex.is_match(line, true).unwrap_or(false))
+            .count();
+        Some(format!("  ({matched} of {} lines match)", self.lines.len()))
+    }
+}
+
+impl Completer for LiveHint {
+    type Candidate = String;
+}
+
+impl Highlighter for LiveHint {}
+
+impl Validator for LiveHint {}
+
+impl Helper for LiveHint {}
+
+/// Marks the chars of `range` with `^`, or the position of an empty range
+/// with `|`.
+fn underline(line: &str, range: &std::ops::Range<usize>) -> String {
+    let column = line[..range.start].chars().count();
+    let width = line[range.clone()].chars().count();
+    if width == 0 {
+        format!("{}|", " ".repeat(column))
+    } else {
+        format!("{}{}", " ".repeat(column), "^".repeat(width))
+    }
+}
+
+fn main() -> Result<ExitCode, DynError> {
+    let mut args = env::args().skip(1);
+    let first = args.next();
+    if first.as_deref() == Some("--explain") {
+        let usage = "usage: playground --explain [--syntax perl|basic|extended] PATTERN";
+        let mut syntax = Syntax::Perl;
+        let mut pattern = args.next();
+        if pattern.as_deref() == Some("--syntax") {
+            syntax = match args.next().as_deref() {
+                Some("perl") => Syntax::Perl,
+                Some("basic") => Syntax::PosixBasic,
+                Some("extended") => Syntax::PosixExtended,
+                _ => {
+                    eprintln!("{usage}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+            pattern = args.next();
+        }
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => {
+                eprintln!("{usage}");
+                return Ok(ExitCode::FAILURE);
+            }
+        };
+        return Ok(match RegexBuilder::new(&pattern).syntax(syntax).explain() {
+            Ok(text) => {
+                print!("{text}");
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprint!("{}", e.diagnostic());
+                ExitCode::FAILURE
+            }
+        });
+    }
+
+    let mut playground = Playground {
+        pattern: String::new(),
+        regex: None,
+        lines: Vec::new(),
+    };
+    if let Some(pattern) = first {
+        playground.set_pattern(&pattern);
+    }
+
+    let mut rl = Editor::<LiveHint, FileHistory>::new()?;
+    loop {
+        rl.set_helper(Some(LiveHint {
+            lines: playground.lines.clone(),
+            is_pattern: false,
+        }));
+        let input = match rl.readline("regex> ") {
+            Ok(input) => input,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        rl.add_history_entry(input.as_str())?;
+
+        match input.split_once(' ').unwrap_or((&input, "")) {
+            (":p", pattern) => playground.set_pattern(pattern),
+            (":e", _) => {
+                let pattern = playground.pattern.clone();
+                if let Some(hint) = rl.helper_mut() {
+                    hint.is_pattern = true;
+                }
+                match rl.readline_with_initial("pattern> ", (&pattern, "")) {
+                    Ok(pattern) => playground.set_pattern(&pattern),
+                    Err(ReadlineError::Interrupted) => {}
+                    Err(ReadlineError::Eof) => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            (":code", _) => playground.print_code(),
+            (":explain", _) => playground.print_explanation(),
+            (":lines", _) => playground.run_all(),
+            (":clear", _) => playground.lines.clear(),
+            (":help", _) => println!("{HELP}"),
+            (":q", _) => break,
+            _ => {
+                playground.run(&input);
+                playground.lines.push(input);
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,421 @@
+//! C interface of the engine, declared in `include/rz_regex.h`. Expressions,
+//! captures and errors are opaque handles allocated by this library and
+//! released with their `_free` function.
+//!
+//! Subjects are passed as a pointer and a length in bytes, must be valid
+//! UTF-8, and all offsets returned are byte offsets into them. Functions
+//! taking `rz_error **error` store a new error there on failure if it is not
+//! null. Matching is depth-first, so that backreferences are supported.
+//!
+//! No call unwinds into C: a panic inside the library is reported as a
+//! failure with an error, like any other.
+
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    fmt::Display,
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice, str,
+};
+
+use crate::engine::Regex;
+
+/// Message of a failed call, as `rz_error`.
+#[derive(Debug)]
+pub struct RzError {
+    message: CString,
+}
+
+/// Groups of a match, as `rz_captures`.
+#[derive(Debug)]
+pub struct RzCaptures {
+    groups: Vec<Option<Range<usize>>>,
+}
+
+/// Stores an error into `error` unless it is null.
+unsafe fn set_error(error: *mut *mut RzError, message: impl Display) {
+    if error.is_null() {
+        return;
+    }
+    let message = message.to_string().replace('\0', "\\0");
+    let message = CString::new(message).expect("NUL bytes are escaped");
+    *error = Box::into_raw(Box::new(RzError { message }));
+}
+
+/// Runs `f`, and stores an error and returns `on_panic` if it panics, since
+/// unwinding out of an `extern "C"` function aborts the process.
+unsafe fn guard<T>(error: *mut *mut RzError, on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        set_error(error, format_args!("internal error: {message}"));
+        on_panic
+    })
+}
+
+/// Returns the subject, which may be null if `len` is 0.
+unsafe fn subject<'a>(ptr: *const u8, len: usize) -> Result<&'a str, &'static str> {
+    if len == 0 {
+        return Ok("");
+    }
+    if ptr.is_null() {
+        return Err("subject is null");
+    }
+    str::from_utf8(slice::from_raw_parts(ptr, len)).map_err(|_| "subject is not valid UTF-8")
+}
+
+/// Returns the expression and the subject, reporting failures with `-1`.
+unsafe fn arguments<'a>(
+    regex: *const Regex,
+    ptr: *const u8,
+    len: usize,
+    error: *mut *mut RzError,
+) -> Result<(&'a Regex, &'a str), c_int> {
+    let regex = match regex.as_ref() {
+        Some(regex) => regex,
+        None => {
+            set_error(error, "regex is null");
+            return Err(-1);
+        }
+    };
+    match subject(ptr, len) {
+        Ok(line) => Ok((regex, line)),
+        Err(e) => {
+            set_error(error, e);
+            Err(-1)
+        }
+    }
+}
+
+/// Runs `captures` on the subject, reporting failures with `-1`.
+unsafe fn captures(
+    regex: *const Regex,
+    ptr: *const u8,
+    len: usize,
+    error: *mut *mut RzError,
+) -> Result<Option<Vec<Option<Range<usize>>>>, c_int> {
+    let (regex, line) = arguments(regex, ptr, len, error)?;
+    regex.captures(line, true).map_err(|e| {
+        set_error(error, e);
+        -1
+    })
+}
+
+/// Compiles the NUL-terminated `pattern`. Returns null on failure.
+///
+/// # Safety
+///
+/// `pattern` must be null or a NUL-terminated string, and `error` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rz_regex_new(
+    pattern: *const c_char,
+    error: *mut *mut RzError,
+) -> *mut Regex {
+    guard(error, ptr::null_mut(), || {
+        if pattern.is_null() {
+            set_error(error, "pattern is null");
+            return ptr::null_mut();
+        }
+        let pattern = match CStr::from_ptr(pattern).to_str() {
+            Ok(pattern) => pattern,
+            Err(_) => {
+                set_error(error, "pattern is not valid UTF-8");
+                return ptr::null_mut();
+            }
+        };
+        match Regex::new(pattern) {
+            Ok(regex) => Box::into_raw(Box::new(regex)),
+            Err(e) => {
+                set_error(error, e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Releases an expression. Does nothing if `regex` is null.
+///
+/// # Safety
+///
+/// `regex` must be null or returned by `rz_regex_new` and not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn rz_regex_free(regex: *mut Regex) {
+    guard(ptr::null_mut(), (), || {
+        if !regex.is_null() {
+            drop(Box::from_raw(regex));
+        }
+    })
+}
+
+/// Returns 1 if the subject matches somewhere, 0 if not and -1 on failure.
+///
+/// # Safety
+///
+/// `regex` must be null or a live expression, `subject` must be valid for
+/// reads of `len` bytes unless `len` is 0, and `error` must be null or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rz_regex_is_match(
+    regex: *const Regex,
+    subject: *const u8,
+    len: usize,
+    error: *mut *mut RzError,
+) -> c_int {
+    guard(error, -1, || {
+        let (regex, line) = match arguments(regex, subject, len, error) {
+            Ok(arguments) => arguments,
+            Err(code) => return code,
+        };
+        match regex.is_match(line, true) {
+            Ok(is_match) => is_match as c_int,
+            Err(e) => {
+                set_error(error, e);
+                -1
+            }
+        }
+    })
+}
+
+/// Stores the byte range of the leftmost match into `start` and `end`, which
+/// may be null. Returns 1 if there is a match, 0 if not and -1 on failure.
+///
+/// # Safety
+///
+/// Same as `rz_regex_is_match`, and `start` and `end` must be null or valid
+/// for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rz_regex_find(
+    regex: *const Regex,
+    subject: *const u8,
+    len: usize,
+    start: *mut usize,
+    end: *mut usize,
+    error: *mut *mut RzError,
+) -> c_int {
+    guard(error, -1, || {
+        let range = match captures(regex, subject, len, error) {
+            Ok(Some(mut groups)) => match groups.swap_remove(0) {
+                Some(range) => range,
+                None => {
+                    set_error(error, "match has no range");
+                    return -1;
+                }
+            },
+            Ok(None) => return 0,
+            Err(code) => return code,
+        };
+        if !start.is_null() {
+            *start = range.start;
+        }
+        if !end.is_null() {
+            *end = range.end;
+        }
+        1
+    })
+}
+
+/// Stores the groups of the leftmost match into `captures`, to be released
+/// with `rz_captures_free`. Returns 1 if there is a match, 0 if not and -1 on
+/// failure, and leaves `captures` untouched unless 1 is returned.
+///
+/// # Safety
+///
+/// Same as `rz_regex_is_match`, and `captures` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rz_regex_captures(
+    regex: *const Regex,
+    subject: *const u8,
+    len: usize,
+    captures: *mut *mut RzCaptures,
+    error: *mut *mut RzError,
+) -> c_int {
+    guard(error, -1, || {
+        if captures.is_null() {
+            set_error(error, "captures is null");
+            return -1;
+        }
+        match self::captures(regex, subject, len, error) {
+            Ok(Some(groups)) => {
+                *captures = Box::into_raw(Box::new(RzCaptures { groups }));
+                1
+            }
+            Ok(None) => 0,
+            Err(code) => code,
+        }
+    })
+}
+
+/// Returns the number of groups including the whole match, or 0 if
+/// `captures` is null.
+///
+/// # Safety
+///
+/// `captures` must be null or live.
+#[no_mangle]
+pub unsafe extern "C" fn rz_captures_len(captures: *const RzCaptures) -> usize {
+    guard(ptr::null_mut(), 0, || {
+        captures.as_ref().map_or(0, |caps| caps.groups.len())
+    })
+}
+
+/// Stores the byte range of group `index` into `start` and `end`, which may
+/// be null. Returns 1 if the group participated in the match and 0 if not or
+/// if `index` is out of range.
+///
+/// # Safety
+///
+/// `captures` must be null or live, and `start` and `end` must be null or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn rz_captures_get(
+    captures: *const RzCaptures,
+    index: usize,
+    start: *mut usize,
+    end: *mut usize,
+) -> c_int {
+    guard(ptr::null_mut(), 0, || {
+        let range = match captures.as_ref().and_then(|caps| caps.groups.get(index)) {
+            Some(Some(range)) => range,
+            _ => return 0,
+        };
+        if !start.is_null() {
+            *start = range.start;
+        }
+        if !end.is_null() {
+            *end = range.end;
+        }
+        1
+    })
+}
+
+/// Releases captures. Does nothing if `captures` is null.
+///
+/// # Safety
+///
+/// `captures` must be null or returned by `rz_regex_captures` and not yet
+/// freed.
+#[no_mangle]
+pub unsafe extern "C" fn rz_captures_free(captures: *mut RzCaptures) {
+    guard(ptr::null_mut(), (), || {
+        if !captures.is_null() {
+            drop(Box::from_raw(captures));
+        }
+    })
+}
+
+/// Returns the NUL-terminated message of an error, valid until the error is
+/// freed, or null if `error` is null.
+///
+/// # Safety
+///
+/// `error` must be null or live.
+#[no_mangle]
+pub unsafe extern "C" fn rz_error_message(error: *const RzError) -> *const c_char {
+    guard(ptr::null_mut(), ptr::null(), || {
+        error.as_ref().map_or(ptr::null(), |e| e.message.as_ptr())
+    })
+}
+
+/// Releases an error. Does nothing if `error` is null.
+///
+/// # Safety
+///
+/// `error` must be null or returned through an `rz_error **` argument and
+/// not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn rz_error_free(error: *mut RzError) {
+    guard(ptr::null_mut(), (), || {
+        if !error.is_null() {
+            drop(Box::from_raw(error));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        guard, rz_captures_free, rz_captures_get, rz_captures_len, rz_error_free, rz_error_message,
+        rz_regex_captures, rz_regex_find, rz_regex_free, rz_regex_is_match, rz_regex_new,
+    };
+    use std::{ffi::CStr, ptr};
+
+    #[test]
+    fn test_capi() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let regex = rz_regex_new(c"(a+)(x)?b".as_ptr(), &mut error);
+            assert!(!regex.is_null() && error.is_null());
+
+            let line = "\u{3042}aab";
+            let (mut start, mut end) = (0, 0);
+            assert_eq!(
+                rz_regex_find(
+                    regex,
+                    line.as_ptr(),
+                    line.len(),
+                    &mut start,
+                    &mut end,
+                    &mut error
+                ),
+                1
+            );
+            assert_eq!((start, end), (3, 6));
+
+            let mut caps = ptr::null_mut();
+            assert_eq!(
+                rz_regex_captures(regex, line.as_ptr(), line.len(), &mut caps, &mut error),
+                1
+            );
+            assert_eq!(rz_captures_len(caps), 3);
+            assert_eq!(rz_captures_get(caps, 1, &mut start, &mut end), 1);
+            assert_eq!((start, end), (3, 5));
+            assert_eq!(rz_captures_get(caps, 2, &mut start, &mut end), 0);
+            assert_eq!(rz_captures_get(caps, 3, &mut start, &mut end), 0);
+            rz_captures_free(caps);
+
+            assert_eq!(rz_regex_is_match(regex, ptr::null(), 0, &mut error), 0);
+            let invalid = [0xFF];
+            assert_eq!(
+                rz_regex_is_match(regex, invalid.as_ptr(), 1, &mut error),
+                -1
+            );
+            let message = CStr::from_ptr(rz_error_message(error));
+            assert_eq!(message.to_str().unwrap(), "subject is not valid UTF-8");
+            rz_error_free(error);
+            rz_regex_free(regex);
+        }
+    }
+
+    #[test]
+    fn test_capi_error() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            assert!(rz_regex_new(c"(ab".as_ptr(), &mut error).is_null());
+            assert!(!error.is_null());
+            assert!(!CStr::from_ptr(rz_error_message(error)).is_empty());
+            rz_error_free(error);
+
+            assert!(rz_regex_new(ptr::null(), ptr::null_mut()).is_null());
+            assert_eq!(
+                rz_regex_is_match(ptr::null(), ptr::null(), 0, ptr::null_mut()),
+                -1
+            );
+        }
+    }
+
+    #[test]
+    fn test_capi_panic() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            assert_eq!(guard(&mut error, -1, || panic!("boom")), -1);
+            let message = CStr::from_ptr(rz_error_message(error));
+            assert_eq!(message.to_str().unwrap(), "internal error: boom");
+            rz_error_free(error);
+            assert_eq!(guard(ptr::null_mut(), 0, || panic!("boom")), 0);
+        }
+    }
+}
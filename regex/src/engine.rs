@@ -1,31 +1,355 @@
-use std::error::Error;
+use std::{error::Error, fmt::Display, ops::Range};
 
+mod bitparallel;
+mod cache;
+mod class;
 mod codegen;
+mod dfa;
 mod evaluator;
+pub mod explain;
+#[cfg(test)]
+mod fuzz;
+pub mod glob;
+pub mod jit;
+pub mod language;
+pub mod lexer;
+pub mod parallel;
 mod parser;
+pub mod sample;
+mod serialize;
+pub mod stream;
+mod unicode;
+
+pub use cache::Cache;
+pub use codegen::{CodeGenError, Instruction};
+pub use dfa::DfaError;
+pub use evaluator::{EvalError, MatchKind, MatchStats};
+pub use parser::{ParseError, ParseErrorKind, Syntax};
+pub use serialize::{LoadError, FORMAT_VERSION};
+pub use unicode::UNICODE_VERSION;
+
+#[derive(Debug)]
+pub enum RegexError {
+    Parse(ParseError),
+    CodeGen(CodeGenError),
+    Eval(EvalError),
+    Dfa(DfaError),
+}
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegexError::Parse(e) => write!(f, "{e}"),
+            RegexError::CodeGen(e) => write!(f, "{e}"),
+            RegexError::Eval(e) => write!(f, "{e}"),
+            RegexError::Dfa(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for RegexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RegexError::Parse(e) => Some(e),
+            RegexError::CodeGen(e) => Some(e),
+            RegexError::Eval(e) => Some(e),
+            RegexError::Dfa(e) => Some(e),
+        }
+    }
+}
+
+impl From<ParseError> for RegexError {
+    fn from(e: ParseError) -> Self {
+        RegexError::Parse(e)
+    }
+}
+
+impl From<CodeGenError> for RegexError {
+    fn from(e: CodeGenError) -> Self {
+        RegexError::CodeGen(e)
+    }
+}
+
+impl From<EvalError> for RegexError {
+    fn from(e: EvalError) -> Self {
+        RegexError::Eval(e)
+    }
+}
+
+impl From<DfaError> for RegexError {
+    fn from(e: DfaError) -> Self {
+        RegexError::Dfa(e)
+    }
+}
+
+/// Compiles an expression with options.
+#[derive(Debug, Clone)]
+pub struct RegexBuilder {
+    expr: String,
+    options: parser::ParseOptions,
+    match_kind: MatchKind,
+}
+
+impl RegexBuilder {
+    pub fn new(expr: &str) -> RegexBuilder {
+        RegexBuilder {
+            expr: expr.to_string(),
+            options: parser::ParseOptions::default(),
+            match_kind: MatchKind::default(),
+        }
+    }
+
+    /// Ignores unescaped whitespace and comments from `#` to the end of the
+    /// line, as the `(?x)` flag does. `\ ` and `\#` match themselves.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.options.verbose = verbose;
+        self
+    }
+
+    /// Selects the dialect of the expression, such as the POSIX basic
+    /// syntax of `grep` and `sed`.
+    pub fn syntax(mut self, syntax: Syntax) -> Self {
+        self.options.syntax = syntax;
+        self
+    }
+
+    /// Makes `.` match an extended grapheme cluster, a user-perceived
+    /// character such as `e` followed by a combining accent, instead of a
+    /// single char.
+    pub fn grapheme(mut self, grapheme: bool) -> Self {
+        self.options.grapheme = grapheme;
+        self
+    }
+
+    /// Selects which match `captures` reports, such as the longest one as
+    /// POSIX tools do.
+    pub fn match_kind(mut self, kind: MatchKind) -> Self {
+        self.match_kind = kind;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        let ast = parser::parse_with(&self.expr, &self.options)?;
+        let mut regex = Regex::from_ast(&ast)?;
+        regex.match_kind = self.match_kind;
+        Ok(regex)
+    }
+
+    /// Describes the expression as parsed with the options of the builder,
+    /// as [`explain::explain`] does for the default ones.
+    pub fn explain(&self) -> Result<String, ParseError> {
+        let ast = parser::parse_with(&self.expr, &self.options)?;
+        Ok(explain::explain_ast(&ast))
+    }
+}
+
+/// Byte ranges of a match at index 0 and of each capture group, `None` for
+/// the groups that did not participate.
+pub type Groups = Vec<Option<Range<usize>>>;
 
-pub type DynError = Box<dyn Error + 'static>;
+/// Evaluator of `Regex::is_match_engine`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    DepthFirst,
+    BreadthFirst,
+    /// simulation of the Glushkov automaton with bit operations, available
+    /// for short expressions without backreferences or lookaround
+    BitParallel,
+    /// the bit-parallel evaluator whenever the expression allows it, and the
+    /// depth-first one otherwise
+    Auto,
+}
+
+/// A compiled expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    code: Vec<Instruction>,
+    group_count: usize,
+    match_kind: MatchKind,
+    bit_parallel: Option<bitparallel::BitParallel>,
+}
+
+impl Regex {
+    pub fn new(expr: &str) -> Result<Regex, RegexError> {
+        let ast = parser::parse(expr)?;
+        Regex::from_ast(&ast)
+    }
+
+    fn from_ast(ast: &parser::AST) -> Result<Regex, RegexError> {
+        let code = codegen::get_code(ast)?;
+        let group_count = code
+            .iter()
+            .filter_map(|inst| match inst {
+                Instruction::Save(slot) => Some(slot / 2),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        Ok(Regex {
+            code,
+            group_count,
+            match_kind: MatchKind::default(),
+            bit_parallel: bitparallel::BitParallel::new(ast),
+        })
+    }
+
+    pub fn is_match(&self, line: &str, is_depth: bool) -> Result<bool, RegexError> {
+        self.is_match_with(line, is_depth, &mut Cache::new())
+    }
+
+    /// Same as `is_match`, but reuses the buffers of `cache`, so that
+    /// repeated matches perform no heap allocation once the cache has grown
+    /// to the program and the line.
+    pub fn is_match_with(
+        &self,
+        line: &str,
+        is_depth: bool,
+        cache: &mut Cache,
+    ) -> Result<bool, RegexError> {
+        let engine = if is_depth {
+            Engine::DepthFirst
+        } else {
+            Engine::BreadthFirst
+        };
+        self.is_match_engine(line, engine, cache)
+    }
 
-pub fn do_matching(expr: &str, line: &str, is_depth: bool) -> Result<bool, DynError> {
+    /// Returns the evaluator that `Engine::Auto` stands for.
+    pub fn auto_engine(&self) -> Engine {
+        match &self.bit_parallel {
+            Some(_) => Engine::BitParallel,
+            None => Engine::DepthFirst,
+        }
+    }
+
+    /// Same as `is_match_with`, but with the given evaluator. The work it
+    /// did is left in [`Cache::stats`].
+    pub fn is_match_engine(
+        &self,
+        line: &str,
+        engine: Engine,
+        cache: &mut Cache,
+    ) -> Result<bool, RegexError> {
+        match (engine, &self.bit_parallel) {
+            (Engine::DepthFirst, _) => Ok(cache::is_match(&self.code, line, true, cache)?),
+            (Engine::BreadthFirst, _) => Ok(cache::is_match(&self.code, line, false, cache)?),
+            (Engine::BitParallel, Some(bit_parallel)) => {
+                let mut stats = MatchStats::default();
+                let is_match = bit_parallel.is_match(line, &mut stats);
+                cache.set_stats(stats);
+                Ok(is_match)
+            }
+            (Engine::BitParallel, None) => Err(EvalError::BitParallelUnsupported.into()),
+            (Engine::Auto, _) => self.is_match_engine(line, self.auto_engine(), cache),
+        }
+    }
+
+    /// Returns the byte range of the leftmost match at index 0 followed by
+    /// the range of each capture group, or `None` for groups that did not
+    /// participate in the match. Which match is reported is selected by the
+    /// match kind, and both evaluators agree on it.
+    pub fn captures(&self, line: &str, is_depth: bool) -> Result<Option<Groups>, RegexError> {
+        Ok(self.captures_with_stats(line, is_depth)?.0)
+    }
+
+    /// Same as `captures`, and also returns the work done by the evaluator,
+    /// such as the number of instructions evaluated and of threads pruned.
+    pub fn captures_with_stats(
+        &self,
+        line: &str,
+        is_depth: bool,
+    ) -> Result<(Option<Groups>, MatchStats), RegexError> {
+        let chars = line.chars().collect::<Vec<char>>();
+        let mut scratch = evaluator::Scratch::default();
+        let caps = evaluator::eval_captures_with(
+            &self.code,
+            &chars,
+            is_depth,
+            self.match_kind,
+            &mut scratch,
+        )?;
+        let stats = scratch.stats;
+        let caps = match caps {
+            Some(caps) => caps,
+            None => return Ok((None, stats)),
+        };
+
+        let mut offsets = line.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+        offsets.push(line.len());
+        let groups = (0..=self.group_count)
+            .map(|n| match (caps.get(2 * n), caps.get(2 * n + 1)) {
+                (Some(Some(start)), Some(Some(end))) if start <= end => {
+                    Some(offsets[*start]..offsets[*end])
+                }
+                _ => None,
+            })
+            .collect();
+        Ok((Some(groups), stats))
+    }
+
+    /// Returns the number of states the evaluator visits while `captures`
+    /// looks for the leftmost match, to compare the work of the depth-first
+    /// and breadth-first searches.
+    pub fn visited_states(&self, line: &str, is_depth: bool) -> Result<usize, RegexError> {
+        Ok(self.captures_with_stats(line, is_depth)?.1.threads_created)
+    }
+
+    /// Returns the compiled program.
+    pub fn code(&self) -> &[Instruction] {
+        &self.code
+    }
+
+    /// Finds the match with the fewest inserted, deleted or substituted
+    /// chars, allowing at most `max_cost` of them.
+    pub fn approx_match(
+        &self,
+        line: &str,
+        max_cost: usize,
+    ) -> Result<Option<ApproxMatch>, RegexError> {
+        let chars = line.chars().collect::<Vec<char>>();
+        let m = match evaluator::eval_approx(&self.code, &chars, max_cost)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        let mut offsets = line.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+        offsets.push(line.len());
+        Ok(Some(ApproxMatch {
+            range: offsets[m.start]..offsets[m.end],
+            cost: m.cost,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApproxMatch {
+    /// byte range of the match
+    pub range: Range<usize>,
+    /// number of edits needed to match
+    pub cost: usize,
+}
+
+pub fn do_matching(expr: &str, line: &str, is_depth: bool) -> Result<bool, RegexError> {
     let ast = match parser::parse(expr) {
         Ok(ast) => ast,
-        Err(parser::ParseError::Empty) => {
+        Err(e) if e.kind == ParseErrorKind::Empty => {
             if line.len() == 0 {
                 return Ok(true);
             } else {
-                return Err(Box::new(parser::ParseError::Empty));
+                return Err(e.into());
             }
         }
-        Err(it) => return Err(Box::new(it)),
+        Err(e) => return Err(e.into()),
     };
-    let code = codegen::get_code(&ast)?;
-    let line = line.chars().collect::<Vec<char>>();
-    Ok(evaluator::eval(&code, &line, is_depth)?)
+    Regex::from_ast(&ast)?.is_match(line, is_depth)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::engine::do_matching;
+    use crate::engine::{
+        do_matching, ApproxMatch, Cache, Engine, EvalError, MatchKind, ParseErrorKind, Regex,
+        RegexBuilder, RegexError, Syntax,
+    };
     use rstest::*;
 
     #[rstest]
@@ -33,6 +357,10 @@ mod tests {
     #[case("|b", "bbb")]
     #[case("?b", "bbb")]
     #[case("+b", "bbb")]
+    #[case("(a)\\2", "aa")]
+    #[case("(?<=a+)b", "ab")]
+    #[case("(?a)", "a")]
+    #[case("(?<a)", "a")]
     fn test_err(#[case] expr: &str, #[case] line: &str) {
         assert!(do_matching(expr, line, true).is_err());
         assert!(do_matching(expr, line, false).is_err());
@@ -62,7 +390,6 @@ mod tests {
     #[case("(ab|cd)+", "aaacbcbdcd")]
     #[case("a(bc)?", "a")]
     #[case("^^^^^^^^^^^^a$$$$$$$$$$$$$$$$$$$$$$$$$$", "a")]
-    // #[case("^.a$", "⛹‍♂a")]
     fn test_match_success(#[case] expr: &str, #[case] line: &str) {
         assert!(do_matching(expr, line, true).unwrap());
         assert!(do_matching(expr, line, false).unwrap());
@@ -79,4 +406,514 @@ mod tests {
         assert!(!do_matching(expr, line, true).unwrap());
         assert!(!do_matching(expr, line, false).unwrap());
     }
+
+    #[rstest]
+    #[case("a*$", "b", 1..1)]
+    #[case("$", "abc", 3..3)]
+    #[case("a*", "", 0..0)]
+    #[case("^$", "", 0..0)]
+    #[case("b*", "aaa", 0..0)]
+    fn test_match_empty(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expected: std::ops::Range<usize>,
+    ) {
+        // a match may start at the end of the line
+        let regex = Regex::new(expr).unwrap();
+        for is_depth in [true, false] {
+            assert!(do_matching(expr, line, is_depth).unwrap());
+            let caps = regex.captures(line, is_depth).unwrap().unwrap();
+            assert_eq!(caps[0], Some(expected.clone()));
+        }
+    }
+
+    #[rstest]
+    #[case("(a|b)+\\1", "xabb", true)]
+    #[case("^(a+)b\\1$", "aabaa", true)]
+    #[case("^(a+)b\\1$", "aaba", false)]
+    #[case("(a)|b\\1", "b", false)]
+    #[case("((a|b)c)\\2\\1", "bcbbc", true)]
+    fn test_backref(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert!(do_matching(expr, line, false).is_err());
+    }
+
+    #[rstest]
+    #[case("a(?=b)", "ab", true)]
+    #[case("a(?=b)", "ac", false)]
+    #[case("a(?!b)", "ab", false)]
+    #[case("a(?!b)", "ac", true)]
+    #[case("(?<=a)b", "ab", true)]
+    #[case("(?<=a)b", "cb", false)]
+    #[case("(?<!a)b", "ab", false)]
+    #[case("(?<!a)b", "cb", true)]
+    #[case("(?<=ab|c)d", "abd", true)]
+    #[case("(?<=ab|c)d", "cd", true)]
+    #[case("(?<=ab|c)d", "bd", false)]
+    #[case("(?<=^a?)b", "ab", true)]
+    #[case("(?<=^a?)b", "cab", false)]
+    #[case("^(?=.*1)(?=.*a).+$", "x1a", true)]
+    #[case("^(?=.*1)(?=.*a).+$", "x1b", false)]
+    #[case("a(?=b(?<!ab))", "ab", false)]
+    fn test_look_around(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, line, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("(?>a+)b", "aab", true)]
+    #[case("(?>a+)a", "aaa", false)]
+    #[case("a*+a", "aaa", false)]
+    #[case("a++b", "aab", true)]
+    #[case("a?+a", "a", false)]
+    #[case("a?+a", "aa", true)]
+    #[case("(?>ab|a)c", "abc", true)]
+    #[case("(?>a|ab)c", "abc", false)]
+    #[case("^(?>(a+)+)$", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaab", false)]
+    fn test_atomic(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert!(do_matching(expr, line, false).is_err());
+    }
+
+    #[rstest]
+    #[case("(?=(ab))a", "ab", vec![Some(0..1), Some(0..2)])]
+    #[case("b(c)", "abcd", vec![Some(1..3), Some(2..3)])]
+    #[case("(a)|(b)", "b", vec![Some(0..1), None, Some(0..1)])]
+    #[case("a|ab", "ab", vec![Some(0..1)])]
+    #[case("(ab|a)(b?)", "ab", vec![Some(0..2), Some(0..2), Some(2..2)])]
+    #[case("(a|ab)(c|b)", "ab", vec![Some(0..2), Some(0..1), Some(1..2)])]
+    #[case("あ(い*)(う)?", "xあいい", vec![Some(1..10), Some(4..10), None])]
+    #[case("()", "", vec![Some(0..0), Some(0..0)])]
+    #[case("(a*)(a*)", "aaa", vec![Some(0..3), Some(0..3), Some(3..3)])]
+    #[case("(a|ab)(b*)", "abb", vec![Some(0..3), Some(0..1), Some(1..3)])]
+    fn test_captures(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expected: Vec<Option<std::ops::Range<usize>>>,
+    ) {
+        let regex = Regex::new(expr).unwrap();
+        assert_eq!(regex.captures(line, true).unwrap(), Some(expected.clone()));
+        assert_eq!(regex.captures(line, false).unwrap(), Some(expected));
+    }
+
+    #[rstest]
+    #[case("receive", "I recieve mail", 2, Some((2..9, 2)))]
+    #[case("receive", "I recieve mail", 1, None)]
+    #[case("receive", "I receive mail", 2, Some((2..9, 0)))]
+    #[case("colou?r", "the colr", 1, Some((4..8, 1)))]
+    #[case("^abc$", "abxc", 1, Some((0..4, 1)))]
+    #[case("a.c", "ac", 1, Some((0..2, 1)))]
+    #[case("(ab)+", "xb", 1, Some((0..2, 1)))]
+    #[case("(ab)+", "xyz", 1, None)]
+    #[case("^.$", "ab", 3, Some((0..2, 1)))]
+    #[case("^abc$", "abcx", 2, Some((0..4, 1)))]
+    #[case("^a.c$", "axxc", 1, Some((0..4, 1)))]
+    #[case("^ab$", "axb", 1, Some((0..3, 1)))]
+    #[case("abc", "xyz", usize::MAX, Some((0..3, 3)))]
+    #[case("^a{3}$", "", 100_000_000_000, Some((0..0, 3)))]
+    fn test_approx(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] max_cost: usize,
+        #[case] expected: Option<(std::ops::Range<usize>, usize)>,
+    ) {
+        let regex = Regex::new(expr).unwrap();
+        assert_eq!(
+            regex.approx_match(line, max_cost).unwrap(),
+            expected.map(|(range, cost)| ApproxMatch { range, cost })
+        );
+    }
+
+    #[rstest]
+    #[case("a|*b", ParseErrorKind::NoPrev, 2..3)]
+    #[case("ab)", ParseErrorKind::InvalidRightParen, 2..3)]
+    #[case("x(ab", ParseErrorKind::NoRightParen, 1..2)]
+    #[case("a\\q", ParseErrorKind::InvalidEscape('q'), 1..3)]
+    #[case("(a)\\2", ParseErrorKind::InvalidBackref(2), 3..5)]
+    #[case("(?y)", ParseErrorKind::InvalidGroup, 0..3)]
+    #[case("あ\\", ParseErrorKind::TrailingBackslash, 3..4)]
+    #[case("a[bc", ParseErrorKind::NoRightBracket, 1..2)]
+    #[case("[[:alfa:]]", ParseErrorKind::InvalidClass, 1..9)]
+    #[case("x[z-a]", ParseErrorKind::InvalidRange, 2..5)]
+    #[case("^[\\d]$", ParseErrorKind::InvalidEscape('d'), 2..4)]
+    #[case("[^\\x00]", ParseErrorKind::InvalidEscape('x'), 2..4)]
+    #[case("[a-\\n]", ParseErrorKind::InvalidEscape('n'), 3..5)]
+    #[case("a{3,1}", ParseErrorKind::InvalidRepeat, 1..6)]
+    #[case("{2}", ParseErrorKind::NoPrev, 0..1)]
+    #[case("\\p{Klingon}", ParseErrorKind::InvalidProperty, 0..11)]
+    #[case("a\\p{L", ParseErrorKind::NoRightBrace, 3..4)]
+    #[case("", ParseErrorKind::Empty, 0..0)]
+    fn test_parse_error(
+        #[case] expr: &str,
+        #[case] kind: ParseErrorKind,
+        #[case] span: std::ops::Range<usize>,
+    ) {
+        match Regex::new(expr) {
+            Err(RegexError::Parse(e)) => {
+                assert_eq!(e.kind, kind);
+                assert_eq!(e.span, span);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let e = match Regex::new("(あ|+b)") {
+            Err(RegexError::Parse(e)) => e,
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(
+            e.diagnostic(),
+            "error: no previous expression\n  |\n  | (あ|+b)\n  |    ^\n"
+        );
+    }
+
+    #[rstest]
+    #[case("(?x) a b c", "abc", true)]
+    #[case("(?x) a b c", "a b c", false)]
+    #[case("(?x) a\\ b", "a b", true)]
+    #[case("(?x) a\\#b # comment", "a#b", true)]
+    #[case("(?x)\n  (ab)+  # one or more\n  c   # then c\n", "ababc", true)]
+    #[case("(a b(?x) c ) d", "a bc d", true)]
+    #[case("(a b(?x) c ) d", "a b c d", false)]
+    #[case("a b#c", "a b#c", true)]
+    fn test_verbose(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, line, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_builder_verbose() {
+        let regex = RegexBuilder::new("^ a+ # as\n b $")
+            .verbose(true)
+            .build()
+            .unwrap();
+        assert!(regex.is_match("aab", true).unwrap());
+        assert!(!regex.is_match("aa b", true).unwrap());
+    }
+
+    #[rstest]
+    #[case("[abc]+", "xcab", true)]
+    #[case("^[^abc]$", "d", true)]
+    #[case("^[^abc]$", "b", false)]
+    #[case("^[a-cx-z]+$", "abzy", true)]
+    #[case("^[]a]+$", "]a]", true)]
+    #[case("^[a-]+$", "-a", true)]
+    #[case("^[[:alpha:]_][[:alnum:]_]*$", "_x1", true)]
+    #[case("^[[:alpha:]_][[:alnum:]_]*$", "1x", false)]
+    #[case("^[[:space:][:digit:]]+$", "1 2\t3", true)]
+    #[case("^[\\]-]+$", "]-", true)]
+    #[case("^[\\^\\\\\\[]+$", "^\\[", true)]
+    #[case("[あ-う]", "xい", true)]
+    fn test_bracket(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let line = line.replace("\\t", "\t");
+        assert_eq!(do_matching(expr, &line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, &line, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^a{3}$", "aaa", true)]
+    #[case("^a{3}$", "aa", false)]
+    #[case("^(ab){2,}$", "ababab", true)]
+    #[case("^(ab){2,}$", "ab", false)]
+    #[case("^a{1,2}b$", "aab", true)]
+    #[case("^a{1,2}b$", "aaab", false)]
+    #[case("^a{0}b$", "b", true)]
+    #[case("^a{,2}$", "a{,2}", true)]
+    #[case("^a{x}$", "a{x}", true)]
+    fn test_interval(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, line, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^a{2}$", "aa", true)]
+    #[case("^a{2}$", "a{2}", false)]
+    #[case("^a\\{2\\}$", "a{2}", true)]
+    #[case("^\\[a\\]$", "[a]", true)]
+    #[case("^[a]$", "[a]", false)]
+    #[case("^a]}$", "a]}", true)]
+    #[case("^{$", "{", true)]
+    fn test_perl_metachars(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        // brackets and braces are operators in the default syntax too
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, line, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("[", ParseErrorKind::NoRightBracket)]
+    #[case("a[b", ParseErrorKind::NoRightBracket)]
+    #[case("{3}", ParseErrorKind::NoPrev)]
+    fn test_perl_metachars_err(#[case] expr: &str, #[case] kind: ParseErrorKind) {
+        match Regex::new(expr) {
+            Err(RegexError::Parse(e)) => assert_eq!(e.kind, kind),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[rstest]
+    #[case(Syntax::PosixBasic, "^\\(ab\\)*c$", "ababc", true)]
+    #[case(Syntax::PosixBasic, "^a\\{2\\}$", "aa", true)]
+    #[case(Syntax::PosixBasic, "^a\\{2,\\}$", "a", false)]
+    #[case(Syntax::PosixBasic, "^(a)+|b{1}$", "(a)+|b{1}", true)]
+    #[case(Syntax::PosixBasic, "^ab\\|cd$", "cd", true)]
+    #[case(Syntax::PosixBasic, "a\\+b\\?", "xaab", true)]
+    #[case(Syntax::PosixBasic, "^*a", "*a", true)]
+    #[case(Syntax::PosixBasic, "a^b$c", "a^b$c", true)]
+    #[case(Syntax::PosixBasic, "^\\(a\\)\\1$", "aa", true)]
+    #[case(Syntax::PosixBasic, "^[[:upper:]]\\{2\\}$", "AB", true)]
+    #[case(Syntax::PosixExtended, "^(ab|cd){2}$", "abcd", true)]
+    #[case(Syntax::PosixExtended, "^[[:digit:]]+\\.[[:digit:]]*$", "3.14", true)]
+    #[case(Syntax::PosixExtended, "^[\\n]$", "\\", true)]
+    fn test_syntax(
+        #[case] syntax: Syntax,
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expected: bool,
+    ) {
+        let regex = RegexBuilder::new(expr).syntax(syntax).build().unwrap();
+        assert_eq!(regex.is_match(line, true).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(Syntax::PosixBasic, "a\\{2")]
+    #[case(Syntax::PosixExtended, "(?=a)")]
+    #[case(Syntax::PosixBasic, "\\(a")]
+    fn test_syntax_err(#[case] syntax: Syntax, #[case] expr: &str) {
+        assert!(RegexBuilder::new(expr).syntax(syntax).build().is_err());
+    }
+
+    #[rstest]
+    #[case("a|ab", "xab", vec![Some(1..3)])]
+    #[case("(a|ab)(c|bcd)", "abcd", vec![Some(0..4), Some(0..1), Some(1..4)])]
+    #[case("(a*)(ab)*b", "aabb", vec![Some(0..4), Some(0..1), Some(1..3)])]
+    #[case("x*", "yxx", vec![Some(0..0)])]
+    fn test_leftmost_longest(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] expected: Vec<Option<std::ops::Range<usize>>>,
+    ) {
+        let regex = RegexBuilder::new(expr)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        assert_eq!(regex.captures(line, true).unwrap(), Some(expected.clone()));
+        assert_eq!(regex.captures(line, false).unwrap(), Some(expected));
+    }
+
+    #[rstest]
+    #[case("a|ab", "xab", 1..2, 1..3)]
+    #[case("x|xy*", "xyy", 0..1, 0..3)]
+    #[case("(a|b)*c|(a|b)*", "abab", 0..4, 0..4)]
+    #[case("(?=ab)a|ab.", "abc", 0..1, 0..3)]
+    #[case("^$|.", "", 0..0, 0..0)]
+    fn test_match_kind(
+        #[case] expr: &str,
+        #[case] line: &str,
+        #[case] first: std::ops::Range<usize>,
+        #[case] longest: std::ops::Range<usize>,
+    ) {
+        for (kind, expected) in [
+            (MatchKind::LeftmostFirst, first),
+            (MatchKind::LeftmostLongest, longest),
+        ] {
+            let regex = RegexBuilder::new(expr).match_kind(kind).build().unwrap();
+            for is_depth in [true, false] {
+                let caps = regex.captures(line, is_depth).unwrap().unwrap();
+                assert_eq!(caps[0], Some(expected.clone()), "{kind:?} {is_depth}");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("^\\p{L}+$", "Grüße", true)]
+    #[case("^\\p{L}+$", "abc1", false)]
+    #[case("^\\pL\\pN$", "x٣", true)]
+    #[case("^\\p{Greek}+$", "αβγ", true)]
+    #[case("^\\p{Greek}+$", "αβc", false)]
+    #[case("^\\P{Greek}+$", "abc", true)]
+    #[case("^[\\p{Lu}\\p{Nd}]+$", "A5B", true)]
+    #[case("^[\\p{Lu}\\p{Nd}]+$", "A5b", false)]
+    #[case("^[^\\p{Han}]$", "漢", false)]
+    #[case("\\p{Script=Hiragana}", "カタかな", true)]
+    fn test_property(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(do_matching(expr, line, true).unwrap(), expected);
+        assert_eq!(do_matching(expr, line, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^.a$", "⛹‍♂a", true)]
+    #[case("^..$", "e\u{301}x", true)]
+    #[case("^.$", "🇯🇵", true)]
+    #[case("^.{2}$", "🇯🇵🇫🇷", true)]
+    #[case("^(.)x\\1$", "e\u{301}xe\u{301}", true)]
+    #[case("^.\u{301}", "e\u{301}", false)]
+    #[case("(.|a)b", "e\u{301}b", true)]
+    fn test_grapheme(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let regex = RegexBuilder::new(expr).grapheme(true).build().unwrap();
+        assert_eq!(regex.is_match(line, true).unwrap(), expected);
+        if !expr.contains('\\') {
+            assert_eq!(regex.is_match(line, false).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_grapheme_captures() {
+        let regex = RegexBuilder::new("(.)(.)").grapheme(true).build().unwrap();
+        let expected = vec![Some(0..4), Some(0..3), Some(3..4)];
+        assert_eq!(
+            regex.captures("a\u{301}x", true).unwrap(),
+            Some(expected.clone())
+        );
+        assert_eq!(regex.captures("a\u{301}x", false).unwrap(), Some(expected));
+
+        // `.` consumes single chars unless grapheme mode is enabled
+        assert!(!do_matching("^.a$", "⛹‍♂a", true).unwrap());
+    }
+
+    #[rstest]
+    #[case("a(b|c)*d$", "xabcbd", Engine::BitParallel)]
+    #[case("^[[:alpha:]]{2,4}x", "abcx", Engine::BitParallel)]
+    #[case("(a+)+b", "aaaa", Engine::BitParallel)]
+    #[case("(a)\\1", "xaa", Engine::DepthFirst)]
+    #[case("a{100}", "aaa", Engine::DepthFirst)]
+    fn test_engine(#[case] expr: &str, #[case] line: &str, #[case] expected: Engine) {
+        let regex = Regex::new(expr).unwrap();
+        assert_eq!(regex.auto_engine(), expected);
+
+        let mut cache = Cache::new();
+        let is_match = regex
+            .is_match_engine(line, Engine::DepthFirst, &mut cache)
+            .unwrap();
+        assert_eq!(regex.is_match(line, true).unwrap(), is_match);
+        assert_eq!(
+            regex
+                .is_match_engine(line, Engine::Auto, &mut cache)
+                .unwrap(),
+            is_match
+        );
+        match regex.is_match_engine(line, Engine::BitParallel, &mut cache) {
+            Ok(res) => assert_eq!(res, is_match),
+            Err(RegexError::Eval(EvalError::BitParallelUnsupported)) => {
+                assert_eq!(expected, Engine::DepthFirst)
+            }
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[rstest]
+    #[case("^a{1,1000}$", &"a".repeat(1000), true)]
+    #[case("^a{1,1000}$", &"a".repeat(1001), false)]
+    #[case("^a{1001,}$", &"a".repeat(1001), true)]
+    #[case("^a{1001,}$", &"a".repeat(1000), false)]
+    #[case("^a{1001,}$", &"a".repeat(1040), true)]
+    #[case("^(ab){300,400}c", &format!("{}c", "ab".repeat(350)), true)]
+    #[case("^(ab){300,400}c", &format!("{}c", "ab".repeat(401)), false)]
+    #[case("^(a|aa){200}$", &"a".repeat(300), true)]
+    #[case("^(a|aa){200}$", &"a".repeat(401), false)]
+    #[case("^(a{1001}b){2}$", &format!("{0}b{0}b", "a".repeat(1001)), true)]
+    #[case("^(a{1001}b){2}$", &format!("{0}b{0}ab", "a".repeat(1001)), false)]
+    #[case("^(a?){0,600}b", "aaab", true)]
+    #[case("x(a*){0,1000}y", "xaaay", true)]
+    fn test_counter(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let regex = Regex::new(expr).unwrap();
+        assert!(regex.code.len() < 20);
+        assert_eq!(regex.is_match(line, true).unwrap(), expected);
+        assert_eq!(regex.is_match(line, false).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_counter_captures() {
+        let regex = Regex::new("(a{1001,1500})(a*)").unwrap();
+        let line = "a".repeat(2000);
+        for is_depth in [true, false] {
+            assert_eq!(
+                regex.captures(&line, is_depth).unwrap(),
+                Some(vec![Some(0..2000), Some(0..1500), Some(1500..2000)])
+            );
+        }
+        let regex = RegexBuilder::new("(a{1001,1500})(a*)")
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        assert_eq!(
+            regex.captures(&line, true).unwrap(),
+            Some(vec![Some(0..2000), Some(0..1500), Some(1500..2000)])
+        );
+    }
+
+    #[rstest]
+    #[case("abc", "xxabc", true)]
+    #[case("abc", "xxabd", false)]
+    #[case("(a|a?)+b", "aaaaaaaaaaaaaaaaaaaa", false)]
+    #[case("(a*)*b", "aaaaaaaaaaaaaaaaaaaab", true)]
+    #[case("(?=a)a(?<!b)", "bba", true)]
+    fn test_match_stats(#[case] expr: &str, #[case] line: &str, #[case] is_match: bool) {
+        let regex = Regex::new(expr).unwrap();
+        let n = line.chars().count() + 1;
+        for is_depth in [true, false] {
+            let (caps, stats) = regex.captures_with_stats(line, is_depth).unwrap();
+            assert_eq!(caps.is_some(), is_match);
+            if !is_match {
+                assert_eq!(stats.start_positions, n);
+            }
+            // each start position visits each state at most once
+            assert!(stats.threads_created <= n * n * regex.code().len());
+            assert!(stats.instructions >= stats.start_positions);
+            assert!(stats.peak_queue_len >= 1);
+            assert_eq!(
+                regex.visited_states(line, is_depth).unwrap(),
+                stats.threads_created
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("abc", "xxabc", true)]
+    #[case("abc", "xxabd", false)]
+    #[case("(a*)*b", "aaaa", false)]
+    #[case("(?=a)a(?<!b)", "bba", true)]
+    fn test_is_match_stats(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let regex = Regex::new(expr).unwrap();
+        let mut cache = Cache::new();
+        for engine in [
+            Engine::DepthFirst,
+            Engine::BreadthFirst,
+            Engine::BitParallel,
+        ] {
+            match regex.is_match_engine(line, engine, &mut cache) {
+                Ok(is_match) => assert_eq!(is_match, expected),
+                Err(_) => {
+                    assert_eq!(engine, Engine::BitParallel);
+                    continue;
+                }
+            }
+            let stats = cache.stats();
+            assert!(stats.start_positions >= 1, "{engine:?}");
+            assert!(stats.instructions >= 1, "{engine:?}");
+            assert!(stats.threads_created >= 1, "{engine:?}");
+            // the stats are of the last match only
+            regex.is_match_engine(line, engine, &mut cache).unwrap();
+            assert_eq!(cache.stats(), stats, "{engine:?}");
+        }
+    }
+
+    #[test]
+    fn test_match_stats_modes() {
+        // backtracking stops at the first match, while the threads of the
+        // lockstep search run over all the alternatives
+        let regex = Regex::new("a(b|c)*d|a").unwrap();
+        let (_, depth) = regex.captures_with_stats("abcbcbcbd", true).unwrap();
+        let (_, breadth) = regex.captures_with_stats("abcbcbcbd", false).unwrap();
+        assert_eq!((depth.start_positions, breadth.start_positions), (1, 1));
+        assert_eq!(depth.threads_pruned, 0);
+        assert!(breadth.peak_queue_len > 1);
+
+        // the nested stars reach the same states along many paths
+        let regex = Regex::new("(a*)*b").unwrap();
+        let (_, depth) = regex.captures_with_stats("aaaa", true).unwrap();
+        assert!(depth.threads_pruned > 0);
+        assert_eq!(depth.start_positions, 5);
+    }
 }
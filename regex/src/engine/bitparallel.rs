@@ -0,0 +1,361 @@
+//! Bit-parallel simulation of the Glushkov automaton of an expression. Each
+//! char-consuming leaf of the `AST` is a position and a bit of a `u64`, and
+//! reading a char maps the set of active positions to the positions that may
+//! follow them and accept the char, with a few table lookups and word
+//! operations.
+//!
+//! Only expressions with at most 64 positions, after expanding `{m,n}`, are
+//! supported. `^` and `$` may only anchor the whole expression, and
+//! backreferences, lookaround, atomic groups and grapheme clusters are not
+//! supported.
+
+use super::{class::CharClass, evaluator::MatchStats, parser::AST};
+
+/// maximum number of positions
+const MAX_POSITIONS: usize = 64;
+
+/// positions covered by each lookup table of the follow sets
+const CHUNK_BITS: usize = 8;
+
+#[derive(Debug, Clone)]
+enum Position {
+    Char(char),
+    Class(CharClass),
+    Any,
+}
+
+impl Position {
+    fn accepts(&self, c: char) -> bool {
+        match self {
+            Position::Char(expected) => *expected == c,
+            Position::Class(class) => class.contains(c),
+            Position::Any => true,
+        }
+    }
+}
+
+/// first and last positions of a subexpression, and whether it matches the
+/// empty string
+#[derive(Debug, Clone, Copy)]
+struct Fragment {
+    first: u64,
+    last: u64,
+    nullable: bool,
+}
+
+const EMPTY: Fragment = Fragment {
+    first: 0,
+    last: 0,
+    nullable: true,
+};
+
+#[derive(Debug, Clone)]
+pub struct BitParallel {
+    positions: Vec<Position>,
+    /// `follow[k][b]` is the union of the positions following those in bits
+    /// `CHUNK_BITS * k..` set in `b`
+    follow: Vec<[u64; 1 << CHUNK_BITS]>,
+    /// positions accepting each ASCII char
+    ascii: [u64; 128],
+    first: u64,
+    last: u64,
+    nullable: bool,
+    is_anchored_start: bool,
+    is_anchored_end: bool,
+}
+
+impl BitParallel {
+    /// Returns `None` if the expression is not supported.
+    pub fn new(ast: &AST) -> Option<BitParallel> {
+        let mut body = match ast {
+            AST::Seq(seq) => seq.as_slice(),
+            ast => std::slice::from_ref(ast),
+        };
+        let is_anchored_start = matches!(body.first(), Some(AST::Caret));
+        if is_anchored_start {
+            body = &body[1..];
+        }
+        let is_anchored_end = matches!(body.last(), Some(AST::Dollar));
+        if is_anchored_end {
+            body = &body[..body.len() - 1];
+        }
+        if body.iter().map(count_positions).sum::<Option<usize>>()? > MAX_POSITIONS {
+            return None;
+        }
+
+        let mut builder = Builder {
+            positions: Vec::new(),
+            follow: Vec::new(),
+        };
+        let fragment = builder.build_seq(body);
+
+        let chunks = builder.positions.len().div_ceil(CHUNK_BITS);
+        let follow = (0..chunks)
+            .map(|k| {
+                let mut table = [0; 1 << CHUNK_BITS];
+                for (b, set) in table.iter_mut().enumerate() {
+                    for bit in 0..CHUNK_BITS {
+                        if b & (1 << bit) != 0 {
+                            let pos = CHUNK_BITS * k + bit;
+                            *set |= builder.follow.get(pos).copied().unwrap_or(0);
+                        }
+                    }
+                }
+                table
+            })
+            .collect();
+
+        let mut ascii = [0; 128];
+        for (c, mask) in ascii.iter_mut().enumerate() {
+            *mask = mask_of(&builder.positions, c as u8 as char);
+        }
+
+        Some(BitParallel {
+            positions: builder.positions,
+            follow,
+            ascii,
+            first: fragment.first,
+            last: fragment.last,
+            nullable: fragment.nullable,
+            is_anchored_start,
+            is_anchored_end,
+        })
+    }
+
+    /// Returns `true` if `line` matches somewhere. All the start positions
+    /// run at once, so `stats` counts one instruction per char and the
+    /// active positions as the threads.
+    pub fn is_match(&self, line: &str, stats: &mut MatchStats) -> bool {
+        stats.start_positions += 1;
+        if self.nullable && !self.is_anchored_end {
+            return true;
+        }
+
+        let mut active = 0;
+        for (i, c) in line.chars().enumerate() {
+            let mut next = self.follow_of(active);
+            if !self.is_anchored_start || i == 0 {
+                next |= self.first;
+            }
+            active = next & self.mask(c);
+            let threads = active.count_ones() as usize;
+            stats.instructions += 1;
+            stats.threads_created += threads;
+            stats.peak_queue_len = stats.peak_queue_len.max(threads);
+            if active & self.last != 0 && !self.is_anchored_end {
+                return true;
+            }
+            if active == 0 && self.is_anchored_start {
+                return false;
+            }
+        }
+        active & self.last != 0 || (self.nullable && (!self.is_anchored_start || line.is_empty()))
+    }
+
+    #[inline]
+    fn follow_of(&self, active: u64) -> u64 {
+        self.follow.iter().enumerate().fold(0, |acc, (k, table)| {
+            acc | table[(active >> (CHUNK_BITS * k)) as usize & ((1 << CHUNK_BITS) - 1)]
+        })
+    }
+
+    #[inline]
+    fn mask(&self, c: char) -> u64 {
+        match self.ascii.get(c as usize) {
+            Some(mask) => *mask,
+            None => mask_of(&self.positions, c),
+        }
+    }
+}
+
+fn mask_of(positions: &[Position], c: char) -> u64 {
+    positions
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.accepts(c))
+        .fold(0, |acc, (i, _)| acc | 1 << i)
+}
+
+/// Returns the number of positions of the expression, or `None` if it has
+/// an unsupported operator or more than `MAX_POSITIONS` positions.
+fn count_positions(ast: &AST) -> Option<usize> {
+    let n = match ast {
+        AST::Char(_) | AST::Class(_) | AST::Period => 1,
+        AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Capture(_, e) => count_positions(e)?,
+        AST::Or(e1, e2) => count_positions(e1)? + count_positions(e2)?,
+        AST::Seq(seq) => seq.iter().map(count_positions).sum::<Option<usize>>()?,
+        AST::Repeat(e, min, max) => {
+            let copies = max.or(min.checked_add(1))?;
+            count_positions(e)?.checked_mul(copies)?
+        }
+        AST::Backref(_)
+        | AST::LookAhead(_)
+        | AST::NegLookAhead(_)
+        | AST::LookBehind(_)
+        | AST::NegLookBehind(_)
+        | AST::Atomic(_)
+        | AST::Grapheme
+        | AST::Caret
+        | AST::Dollar => return None,
+    };
+    if n > MAX_POSITIONS {
+        None
+    } else {
+        Some(n)
+    }
+}
+
+struct Builder {
+    positions: Vec<Position>,
+    /// positions that may follow each position
+    follow: Vec<u64>,
+}
+
+impl Builder {
+    fn position(&mut self, position: Position) -> Fragment {
+        let bit = 1 << self.positions.len();
+        self.positions.push(position);
+        self.follow.push(0);
+        Fragment {
+            first: bit,
+            last: bit,
+            nullable: false,
+        }
+    }
+
+    /// Lets the first positions of `to` follow the last positions of `from`.
+    fn link(&mut self, from: u64, to: u64) {
+        for (i, follow) in self.follow.iter_mut().enumerate() {
+            if from & (1 << i) != 0 {
+                *follow |= to;
+            }
+        }
+    }
+
+    fn concat(&mut self, a: Fragment, b: Fragment) -> Fragment {
+        self.link(a.last, b.first);
+        Fragment {
+            first: a.first | if a.nullable { b.first } else { 0 },
+            last: b.last | if b.nullable { a.last } else { 0 },
+            nullable: a.nullable && b.nullable,
+        }
+    }
+
+    fn build_seq(&mut self, seq: &[AST]) -> Fragment {
+        let mut acc = EMPTY;
+        for e in seq {
+            let f = self.build(e);
+            acc = self.concat(acc, f);
+        }
+        acc
+    }
+
+    /// Adds the positions of `ast`, which must have passed `count_positions`.
+    fn build(&mut self, ast: &AST) -> Fragment {
+        match ast {
+            AST::Char(c) => self.position(Position::Char(*c)),
+            AST::Class(class) => self.position(Position::Class(class.clone())),
+            AST::Period => self.position(Position::Any),
+            AST::Capture(_, e) => self.build(e),
+            AST::Seq(seq) => self.build_seq(seq),
+            AST::Or(e1, e2) => {
+                let (f1, f2) = (self.build(e1), self.build(e2));
+                Fragment {
+                    first: f1.first | f2.first,
+                    last: f1.last | f2.last,
+                    nullable: f1.nullable || f2.nullable,
+                }
+            }
+            AST::Star(e) => {
+                let f = self.build(e);
+                self.link(f.last, f.first);
+                Fragment {
+                    nullable: true,
+                    ..f
+                }
+            }
+            AST::Plus(e) => {
+                let f = self.build(e);
+                self.link(f.last, f.first);
+                f
+            }
+            AST::Question(e) => Fragment {
+                nullable: true,
+                ..self.build(e)
+            },
+            // `m` copies followed by `max - min` optional ones, or by a starred
+            // one if unbounded
+            AST::Repeat(e, min, max) => {
+                let mut acc = EMPTY;
+                for _ in 0..*min {
+                    let f = self.build(e);
+                    acc = self.concat(acc, f);
+                }
+                let optional = match max {
+                    Some(max) => max - min,
+                    None => 1,
+                };
+                for _ in 0..optional {
+                    let mut f = self.build(e);
+                    if max.is_none() {
+                        self.link(f.last, f.first);
+                    }
+                    f.nullable = true;
+                    acc = self.concat(acc, f);
+                }
+                acc
+            }
+            _ => unreachable!("rejected by count_positions"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitParallel, MatchStats};
+    use crate::engine::{parser, Regex};
+    use rstest::*;
+
+    #[rstest]
+    #[case("abc", &["abc", "xabcx", "ab", ""])]
+    #[case("^abc$", &["abc", "abcc", "xabc"])]
+    #[case("^a*$", &["", "aaa", "ab"])]
+    #[case("a*", &["", "b"])]
+    #[case("b*$", &["", "a", "ab", "ba"])]
+    #[case("^b*", &["", "a"])]
+    #[case("a(b|c)*d", &["abcbd", "ad", "abx", "xxad"])]
+    #[case("(a+)+b", &["aaab", "aaaa", "b"])]
+    #[case("a?a?a?aaa", &["aa", "aaa", "aaaaa"])]
+    #[case("^(ab|a)(bc|c)?[^b]$", &["abc", "ab", "abcd", "acx", "abb"])]
+    #[case("[[:digit:]]{2,3}x", &["12x", "1x", "a123x", "12"])]
+    #[case("x(ab){2,}y", &["xababy", "xaby", "xabababy"])]
+    #[case("x(ab){0,2}y", &["xy", "xababy", "xabababy"])]
+    #[case("^a.c", &["abc", "a\u{3042}c", "ac", "xabc"])]
+    #[case("\u{3042}+\\p{Greek}", &["\u{3042}\u{3042}\u{3c9}", "\u{3c9}"])]
+    fn test_bit_parallel(#[case] expr: &str, #[case] lines: &[&str]) {
+        let ast = parser::parse(expr).unwrap();
+        let bit_parallel = BitParallel::new(&ast).unwrap();
+        let regex = Regex::new(expr).unwrap();
+        for line in lines {
+            assert_eq!(
+                bit_parallel.is_match(line, &mut MatchStats::default()),
+                regex.is_match(line, true).unwrap(),
+                "{expr} on {line:?}"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("(a)\\1")]
+    #[case("a(?=b)")]
+    #[case("a^b")]
+    #[case("(^a|b)")]
+    #[case("a{65}")]
+    #[case("(ab){32,}")]
+    #[case("a{18446744073709551615,}")]
+    #[case("a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?a?aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa")]
+    fn test_unsupported(#[case] expr: &str) {
+        let ast = parser::parse(expr).unwrap();
+        assert!(BitParallel::new(&ast).is_none());
+    }
+}
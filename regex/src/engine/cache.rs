@@ -0,0 +1,441 @@
+//! Scratch space for matching without heap allocation. A [`Cache`] grows to
+//! the largest program and line it has seen, and is cleared rather than freed
+//! between matches, so repeated matches with the same cache allocate nothing
+//! once it is warm.
+//!
+//! Programs with lookaround, atomic groups, backreferences or counters need
+//! the registers and sub-programs of the general evaluator. Its queues and
+//! visited sets are kept in the cache as well, but the registers holding
+//! captures and counters are still allocated as threads are split.
+
+use std::mem::swap;
+
+use super::{
+    codegen::Instruction,
+    evaluator::{self, EvalError, MatchStats},
+    unicode,
+};
+
+/// Set of program counters below a fixed capacity, with constant time
+/// insertion, lookup and clearing. Iteration follows the insertion order.
+#[derive(Debug, Clone, Default)]
+pub struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+    len: usize,
+}
+
+impl SparseSet {
+    pub fn capacity(&self) -> usize {
+        self.dense.len()
+    }
+
+    /// Empties the set and makes room for values below `capacity`.
+    pub fn reset(&mut self, capacity: usize) {
+        if self.capacity() < capacity {
+            self.dense.resize(capacity, 0);
+            self.sparse.resize(capacity, 0);
+        }
+        self.len = 0;
+    }
+
+    /// Returns `true` if `value` was not in the set.
+    #[inline]
+    pub fn insert(&mut self, value: usize) -> bool {
+        if self.contains(value) {
+            return false;
+        }
+        self.dense[self.len] = value;
+        self.sparse[value] = self.len;
+        self.len += 1;
+        true
+    }
+
+    #[inline]
+    pub fn contains(&self, value: usize) -> bool {
+        let i = self.sparse[value];
+        i < self.len && self.dense[i] == value
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_slice(&self) -> &[usize] {
+        &self.dense[..self.len]
+    }
+}
+
+/// Set of the `(pc, sp)` states of a program over a line, one bit each. Only
+/// the words written since the last clear are cleared, so that clearing costs
+/// no more than the insertions did.
+#[derive(Debug, Clone, Default)]
+pub struct StateSet {
+    bits: Vec<u64>,
+    /// indexes of the nonzero words
+    touched: Vec<usize>,
+    /// number of instructions of the program
+    width: usize,
+}
+
+impl StateSet {
+    /// Empties the set and makes room for the states of a program of `width`
+    /// instructions over `positions` string pointers.
+    pub fn reset(&mut self, width: usize, positions: usize) -> Result<(), EvalError> {
+        self.clear();
+        let states = match width.checked_mul(positions) {
+            Some(states) => states,
+            None => return Err(EvalError::SPOverFlow),
+        };
+        let words = states.div_ceil(64);
+        if self.bits.len() < words {
+            self.bits.resize(words, 0);
+        }
+        self.width = width;
+        Ok(())
+    }
+
+    /// Returns `true` if the state was not in the set.
+    #[inline]
+    pub fn insert(&mut self, pc: usize, sp: usize) -> bool {
+        let state = sp * self.width + pc;
+        let (word, bit) = (state / 64, 1 << (state % 64));
+        if self.bits[word] & bit != 0 {
+            return false;
+        }
+        if self.bits[word] == 0 {
+            self.touched.push(word);
+        }
+        self.bits[word] |= bit;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        for word in self.touched.drain(..) {
+            self.bits[word] = 0;
+        }
+    }
+}
+
+/// Reusable buffers for [`Regex::is_match_with`](super::Regex::is_match_with).
+/// A cache may be shared by any number of expressions, but by one match at a
+/// time.
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    /// the line decoded into chars
+    chars: Vec<char>,
+    /// pending `(pc, sp)` of the depth-first search
+    stack: Vec<(usize, usize)>,
+    /// one bit per `(pc, sp)` visited by the depth-first search
+    visited: Vec<u64>,
+    /// pending program counters while following jumps and splits
+    pcs: Vec<usize>,
+    /// threads at the current and the next string pointer
+    clist: SparseSet,
+    nlist: SparseSet,
+    /// buffers of the general evaluator
+    scratch: evaluator::Scratch,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Returns the work done by the last match with this cache.
+    pub fn stats(&self) -> MatchStats {
+        self.scratch.stats
+    }
+
+    /// Records the work done by a match outside of the cache.
+    pub(super) fn set_stats(&mut self, stats: MatchStats) {
+        self.scratch.stats = stats;
+    }
+}
+
+/// Returns `true` if `line` matches the program somewhere.
+pub fn is_match(
+    inst: &[Instruction],
+    line: &str,
+    is_depth: bool,
+    cache: &mut Cache,
+) -> Result<bool, EvalError> {
+    cache.chars.clear();
+    cache.chars.extend(line.chars());
+    cache.scratch.stats = MatchStats::default();
+
+    let needs_evaluator = inst.iter().any(|i| match i {
+        Instruction::Backref(_)
+        | Instruction::LookAhead(_)
+        | Instruction::NegLookAhead(_)
+        | Instruction::LookBehind(..)
+        | Instruction::NegLookBehind(..)
+        | Instruction::Atomic(_)
+        | Instruction::CounterReset(_) => true,
+        Instruction::AnyGrapheme => !is_depth,
+        _ => false,
+    });
+    if needs_evaluator {
+        evaluator::eval_with(inst, &cache.chars, is_depth, &mut cache.scratch)
+    } else if is_depth {
+        depth_first(inst, cache)
+    } else {
+        breadth_first(inst, cache)
+    }
+}
+
+/// Backtracks from every start position in turn. Without backreferences
+/// whether a state leads to a match does not depend on where the search
+/// started, so the states that failed from one start are not tried again
+/// from the next, and each `(pc, sp)` is visited at most once per line.
+fn depth_first(inst: &[Instruction], cache: &mut Cache) -> Result<bool, EvalError> {
+    let Cache {
+        chars: line,
+        stack,
+        visited,
+        scratch,
+        ..
+    } = cache;
+    let stats = &mut scratch.stats;
+
+    let states = match (line.len() + 1).checked_mul(inst.len()) {
+        Some(states) => states,
+        None => return Err(EvalError::SPOverFlow),
+    };
+    let words = states.div_ceil(64);
+    if visited.len() < words {
+        visited.resize(words, 0);
+    }
+    visited[..words].fill(0);
+    stack.clear();
+
+    for start in 0..=line.len() {
+        stats.start_positions += 1;
+        stack.push((0, start));
+        while let Some((pc, sp)) = stack.pop() {
+            let i = match inst.get(pc) {
+                Some(i) => i,
+                None => return Err(EvalError::InvalidPC),
+            };
+            let state = sp * inst.len() + pc;
+            let (word, bit) = (state / 64, 1 << (state % 64));
+            if visited[word] & bit != 0 {
+                stats.threads_pruned += 1;
+                continue;
+            }
+            visited[word] |= bit;
+            stats.threads_created += 1;
+            stats.instructions += 1;
+
+            match i {
+                Instruction::Char(c) => {
+                    if line.get(sp) == Some(c) {
+                        stack.push((pc + 1, sp + 1));
+                    }
+                }
+                Instruction::Class(class) => {
+                    if line.get(sp).is_some_and(|c| class.contains(*c)) {
+                        stack.push((pc + 1, sp + 1));
+                    }
+                }
+                Instruction::AnyChar => {
+                    if sp < line.len() {
+                        stack.push((pc + 1, sp + 1));
+                    }
+                }
+                Instruction::AnyGrapheme => match unicode::grapheme_len(line, sp) {
+                    0 => {}
+                    len => stack.push((pc + 1, sp + len)),
+                },
+                Instruction::Match => {
+                    stack.clear();
+                    return Ok(true);
+                }
+                Instruction::Jump(addr) => stack.push((*addr, sp)),
+                Instruction::Split(addr1, addr2) => {
+                    stack.push((*addr2, sp));
+                    stack.push((*addr1, sp));
+                    stats.peak_queue_len = stats.peak_queue_len.max(stack.len());
+                }
+                Instruction::AssertHead => {
+                    if sp == 0 {
+                        stack.push((pc + 1, sp));
+                    }
+                }
+                Instruction::AssertTail => {
+                    if sp == line.len() {
+                        stack.push((pc + 1, sp));
+                    }
+                }
+                Instruction::Save(_) => stack.push((pc + 1, sp)),
+                Instruction::Backref(_)
+                | Instruction::LookAhead(_)
+                | Instruction::NegLookAhead(_)
+                | Instruction::LookBehind(..)
+                | Instruction::NegLookBehind(..)
+                | Instruction::Atomic(_)
+                | Instruction::CounterReset(_)
+                | Instruction::CounterInc(_)
+                | Instruction::CounterCheck(..) => {
+                    // left to the evaluator by the caller
+                    stack.clear();
+                    return Err(EvalError::DepthFirstOnly(i.clone()));
+                }
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Runs the threads of all start positions in lockstep in a single pass over
+/// the line. A thread is only its program counter, since neither the captures
+/// nor the priority of the threads matter to whether there is a match.
+fn breadth_first(inst: &[Instruction], cache: &mut Cache) -> Result<bool, EvalError> {
+    let Cache {
+        chars: line,
+        pcs,
+        clist,
+        nlist,
+        scratch,
+        ..
+    } = cache;
+    let stats = &mut scratch.stats;
+    clist.reset(inst.len());
+    nlist.reset(inst.len());
+
+    for sp in 0..=line.len() {
+        stats.start_positions += 1;
+        if add_thread(inst, line, clist, pcs, 0, sp, stats)? {
+            return Ok(true);
+        }
+        stats.peak_queue_len = stats.peak_queue_len.max(clist.as_slice().len());
+        let c = match line.get(sp) {
+            Some(c) => *c,
+            None => break,
+        };
+
+        nlist.clear();
+        for pc in clist.as_slice() {
+            stats.instructions += 1;
+            let is_consumed = match &inst[*pc] {
+                Instruction::Char(expected) => *expected == c,
+                Instruction::Class(class) => class.contains(c),
+                Instruction::AnyChar => true,
+                _ => false,
+            };
+            if is_consumed && add_thread(inst, line, nlist, pcs, pc + 1, sp + 1, stats)? {
+                return Ok(true);
+            }
+        }
+        swap(clist, nlist);
+    }
+    Ok(false)
+}
+
+/// Adds `pc` and the instructions reachable from it without consuming a
+/// char to `threads`. Returns `true` if a `match` instruction is reachable.
+fn add_thread(
+    inst: &[Instruction],
+    line: &[char],
+    threads: &mut SparseSet,
+    pcs: &mut Vec<usize>,
+    pc: usize,
+    sp: usize,
+    stats: &mut MatchStats,
+) -> Result<bool, EvalError> {
+    pcs.clear();
+    pcs.push(pc);
+    while let Some(pc) = pcs.pop() {
+        let i = match inst.get(pc) {
+            Some(i) => i,
+            None => return Err(EvalError::InvalidPC),
+        };
+        if !threads.insert(pc) {
+            stats.threads_pruned += 1;
+            continue;
+        }
+        stats.threads_created += 1;
+        match i {
+            Instruction::Match => return Ok(true),
+            Instruction::Jump(addr) => pcs.push(*addr),
+            Instruction::Split(addr1, addr2) => {
+                pcs.push(*addr2);
+                pcs.push(*addr1);
+            }
+            Instruction::AssertHead if sp == 0 => pcs.push(pc + 1),
+            Instruction::AssertTail if sp == line.len() => pcs.push(pc + 1),
+            Instruction::Save(_) => pcs.push(pc + 1),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, SparseSet, StateSet};
+    use crate::engine::{Engine, Regex};
+    use rstest::*;
+
+    #[test]
+    fn test_sparse_set() {
+        let mut set = SparseSet::default();
+        set.reset(8);
+        assert!(set.insert(5));
+        assert!(set.insert(2));
+        assert!(!set.insert(5));
+        assert!(set.contains(2) && !set.contains(3));
+        assert_eq!(set.as_slice(), &[5, 2]);
+        set.clear();
+        assert!(set.as_slice().is_empty() && !set.contains(5));
+        set.reset(16);
+        assert!(set.insert(15));
+        assert_eq!(set.capacity(), 16);
+    }
+
+    #[test]
+    fn test_state_set() {
+        let mut set = StateSet::default();
+        set.reset(3, 4).unwrap();
+        assert!(set.insert(2, 3));
+        assert!(set.insert(0, 0));
+        assert!(!set.insert(2, 3));
+        assert!(set.insert(2, 0));
+        set.reset(70, 2).unwrap();
+        assert!(set.insert(2, 1));
+        assert!(set.insert(69, 1));
+        assert!(!set.insert(69, 1));
+        set.clear();
+        assert!(set.insert(69, 1));
+        assert!(set.reset(usize::MAX, 2).is_err());
+    }
+
+    #[rstest]
+    #[case("abc", "xxabcxx", true)]
+    #[case("^abc", "xxabc", false)]
+    #[case("a(b|c)*d$", "abcbd", true)]
+    #[case("a(b|c)*d$", "abcbdx", false)]
+    #[case("[[:digit:]]+x", "ab12x", true)]
+    #[case("(a+)+b", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", false)]
+    #[case("a.c", "a\u{3042}c", true)]
+    #[case("(a*)*", "", true)]
+    #[case("\\p{Greek}+$", "abc\u{3c9}", true)]
+    fn test_is_match_with(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let regex = Regex::new(expr).unwrap();
+        let mut cache = Cache::new();
+        for engine in [Engine::DepthFirst, Engine::BreadthFirst] {
+            assert_eq!(
+                regex.is_match_engine(line, engine, &mut cache).unwrap(),
+                expected
+            );
+        }
+        for is_depth in [true, false] {
+            assert_eq!(
+                regex.is_match_with(line, is_depth, &mut cache).unwrap(),
+                expected
+            );
+            assert_eq!(regex.is_match(line, is_depth).unwrap(), expected);
+        }
+    }
+}
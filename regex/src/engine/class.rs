@@ -0,0 +1,98 @@
+use std::fmt::Display;
+
+/// Set of chars stored as sorted, non-overlapping and non-adjacent ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    pub fn new(mut ranges: Vec<(char, char)>) -> CharClass {
+        ranges.sort();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (lo, hi) in ranges {
+            match merged.last_mut() {
+                Some((_, last_hi)) if (lo as u32) <= *last_hi as u32 + 1 => {
+                    *last_hi = (*last_hi).max(hi);
+                }
+                _ => merged.push((lo, hi)),
+            }
+        }
+        CharClass { ranges: merged }
+    }
+
+    pub fn ranges(&self) -> &[(char, char)] {
+        &self.ranges
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        let i = self.ranges.partition_point(|(_, hi)| *hi < c);
+        matches!(self.ranges.get(i), Some((lo, _)) if *lo <= c)
+    }
+
+    pub fn negate(&self) -> CharClass {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        let mut next = Some('\0');
+        for (lo, hi) in self.ranges.iter() {
+            if let Some(start) = next {
+                if start < *lo {
+                    ranges.push((start, prev_char(*lo)));
+                }
+            }
+            next = next_char(*hi);
+        }
+        if let Some(start) = next {
+            ranges.push((start, char::MAX));
+        }
+        CharClass { ranges }
+    }
+}
+
+impl Display for CharClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[")?;
+        for (lo, hi) in self.ranges.iter() {
+            if lo == hi {
+                write!(f, "{}", lo.escape_debug())?;
+            } else {
+                write!(f, "{}-{}", lo.escape_debug(), hi.escape_debug())?;
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{D7FF}' => Some('\u{E000}'),
+        char::MAX => None,
+        _ => char::from_u32(c as u32 + 1),
+    }
+}
+
+fn prev_char(c: char) -> char {
+    match c {
+        '\u{E000}' => '\u{D7FF}',
+        _ => char::from_u32(c as u32 - 1).unwrap_or('\0'),
+    }
+}
+
+/// Returns the class named in a POSIX bracket expression such as `[:alpha:]`.
+pub fn posix_class(name: &str) -> Option<CharClass> {
+    let ranges = match name {
+        "alpha" => vec![('a', 'z'), ('A', 'Z')],
+        "digit" => vec![('0', '9')],
+        "alnum" => vec![('a', 'z'), ('A', 'Z'), ('0', '9')],
+        "upper" => vec![('A', 'Z')],
+        "lower" => vec![('a', 'z')],
+        "space" => vec![(' ', ' '), ('\t', '\r')],
+        "blank" => vec![(' ', ' '), ('\t', '\t')],
+        "punct" => vec![('!', '/'), (':', '@'), ('[', '`'), ('{', '~')],
+        "print" => vec![(' ', '~')],
+        "graph" => vec![('!', '~')],
+        "cntrl" => vec![('\0', '\u{1F}'), ('\u{7F}', '\u{7F}')],
+        "xdigit" => vec![('0', '9'), ('a', 'f'), ('A', 'F')],
+        _ => return None,
+    };
+    Some(CharClass::new(ranges))
+}
@@ -1,6 +1,6 @@
 use std::{error::Error, fmt::Display};
 
-use super::parser::AST;
+use super::{class::CharClass, parser::AST};
 
 #[derive(Debug)]
 pub enum CodeGenError {
@@ -8,6 +8,10 @@ pub enum CodeGenError {
     FailStar,
     FailOr,
     FailQuestion,
+    FailRules,
+    FailLook,
+    FailRepeat,
+    UnboundedLookBehind,
 }
 
 impl Display for CodeGenError {
@@ -18,35 +22,86 @@ impl Display for CodeGenError {
 
 impl Error for CodeGenError {}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Instruction {
     Char(char),
+    /// consume a char contained in the class
+    Class(Box<CharClass>),
     Match,
     Jump(usize),
     Split(usize, usize),
     AnyChar,
+    /// consume an extended grapheme cluster
+    AnyGrapheme,
     AssertHead,
     AssertTail,
+    /// store the string pointer into capture slot
+    Save(usize),
+    Backref(usize),
+    /// succeed if the following sub-program matches at the current position,
+    /// then continue at the address
+    LookAhead(usize),
+    NegLookAhead(usize),
+    /// succeed if the following sub-program, whose length is between the
+    /// first two operands, matches just before the current position, then
+    /// continue at the third operand
+    LookBehind(usize, usize, usize),
+    NegLookBehind(usize, usize, usize),
+    /// run the following sub-program, commit to its first match and continue
+    /// at the address from where that match ended
+    Atomic(usize),
+    /// set the counter to 0
+    CounterReset(usize),
+    /// add 1 to the counter
+    CounterInc(usize),
+    /// continue if the counter is below the first bound, jump to the address
+    /// if it reached the second bound, and split to both otherwise
+    CounterCheck(usize, usize, usize, usize),
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Instruction::Char(c) => write!(f, "char {c}"),
+            Instruction::Class(class) => write!(f, "class {class}"),
             Instruction::Match => write!(f, "match"),
             Instruction::Jump(addr) => write!(f, "jump {:>04}", addr),
             Instruction::Split(addr1, addr2) => write!(f, "split {:>04} {:>04}", addr1, addr2),
             Instruction::AnyChar => write!(f, "period"),
+            Instruction::AnyGrapheme => write!(f, "grapheme"),
             Instruction::AssertHead => write!(f, "caret"),
             Instruction::AssertTail => write!(f, "dollar"),
+            Instruction::Save(slot) => write!(f, "save {slot}"),
+            Instruction::Backref(n) => write!(f, "backref {n}"),
+            Instruction::LookAhead(addr) => write!(f, "lookahead {:>04}", addr),
+            Instruction::NegLookAhead(addr) => write!(f, "neglookahead {:>04}", addr),
+            Instruction::LookBehind(min, max, addr) => {
+                write!(f, "lookbehind {min} {max} {:>04}", addr)
+            }
+            Instruction::NegLookBehind(min, max, addr) => {
+                write!(f, "neglookbehind {min} {max} {:>04}", addr)
+            }
+            Instruction::Atomic(addr) => write!(f, "atomic {:>04}", addr),
+            Instruction::CounterReset(n) => write!(f, "counterreset {n}"),
+            Instruction::CounterInc(n) => write!(f, "counterinc {n}"),
+            Instruction::CounterCheck(n, min, max, addr) => {
+                write!(f, "countercheck {n} {min} {max} {:>04}", addr)
+            }
         }
     }
 }
 
+/// repetitions whose copies would take more instructions than this are
+/// compiled to a counter loop instead, whose count is part of the state of
+/// every thread in the evaluators, the streaming search and the DFA
+const MAX_REPEAT_LEN: usize = 1000;
+
 #[derive(Default, Debug)]
 struct Generator {
     pc: usize,
     insts: Vec<Instruction>,
+    /// number of counters used so far
+    counters: usize,
 }
 
 impl Generator {
@@ -63,12 +118,30 @@ impl Generator {
     fn gen_expr(&mut self, ast: &AST) -> Result<(), CodeGenError> {
         match ast {
             AST::Char(c) => self.gen_single_inst(Instruction::Char(*c))?,
+            AST::Class(class) => {
+                self.gen_single_inst(Instruction::Class(Box::new(class.clone())))?
+            }
             AST::Plus(e) => self.gen_plus(e)?,
             AST::Star(e) => self.gen_star(e)?,
             AST::Question(e) => self.gen_question(e)?,
             AST::Or(e1, e2) => self.gen_or(e1, e2)?,
             AST::Seq(seq) => self.gen_seq(seq)?,
+            AST::Repeat(e, min, max) => self.gen_repeat(e, *min, *max)?,
+            AST::Capture(n, e) => self.gen_capture(*n, e)?,
+            AST::Backref(n) => self.gen_single_inst(Instruction::Backref(*n))?,
+            AST::LookAhead(e) => self.gen_look(Instruction::LookAhead(0), e)?,
+            AST::NegLookAhead(e) => self.gen_look(Instruction::NegLookAhead(0), e)?,
+            AST::LookBehind(e) => {
+                let (min, max) = bounded_width(e)?;
+                self.gen_look(Instruction::LookBehind(min, max, 0), e)?
+            }
+            AST::NegLookBehind(e) => {
+                let (min, max) = bounded_width(e)?;
+                self.gen_look(Instruction::NegLookBehind(min, max, 0), e)?
+            }
+            AST::Atomic(e) => self.gen_look(Instruction::Atomic(0), e)?,
             AST::Period => self.gen_single_inst(Instruction::AnyChar)?,
+            AST::Grapheme => self.gen_single_inst(Instruction::AnyGrapheme)?,
             AST::Caret => self.gen_single_inst(Instruction::AssertHead)?,
             AST::Dollar => self.gen_single_inst(Instruction::AssertTail)?,
         };
@@ -173,6 +246,112 @@ impl Generator {
         Ok(())
     }
 
+    /// L0: codes for e, min times
+    /// L1: split L2, L3
+    /// L2: codes for e
+    ///     split and codes for e, max - min - 1 more times
+    /// L3:
+    ///
+    /// An unbounded repetition ends with the codes for `e*` instead.
+    fn gen_repeat(&mut self, e: &AST, min: usize, max: Option<usize>) -> Result<(), CodeGenError> {
+        if repeat_len(e, min, max)? > MAX_REPEAT_LEN {
+            return self.gen_counter(e, min, max);
+        }
+        for _ in 0..min {
+            self.gen_expr(e)?;
+        }
+        let max = match max {
+            Some(max) => max,
+            None => return self.gen_star(e),
+        };
+
+        let mut splits = Vec::with_capacity(max.saturating_sub(min));
+        for _ in min..max {
+            splits.push(self.pc);
+            self.inc_pc()?;
+            self.insts.push(Instruction::Split(self.pc, 0));
+            self.gen_expr(e)?;
+        }
+        for l1 in splits {
+            match self.insts.get_mut(l1) {
+                Some(Instruction::Split(_, l3)) => {
+                    *l3 = self.pc;
+                }
+                _ => return Err(CodeGenError::FailRepeat),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// L0: counterreset n
+    /// L1: countercheck n min max L3
+    /// L2: codes for e
+    ///     counterinc n
+    ///     jump L1
+    /// L3:
+    ///
+    /// An unbounded repetition counts up to `min` and ends with the codes for
+    /// `e*`.
+    fn gen_counter(&mut self, e: &AST, min: usize, max: Option<usize>) -> Result<(), CodeGenError> {
+        let n = self.counters;
+        self.counters += 1;
+        self.gen_single_inst(Instruction::CounterReset(n))?;
+
+        let l1 = self.pc;
+        self.gen_single_inst(Instruction::CounterCheck(n, min, max.unwrap_or(min), 0))?;
+        self.gen_expr(e)?;
+        self.gen_single_inst(Instruction::CounterInc(n))?;
+        self.gen_single_inst(Instruction::Jump(l1))?;
+
+        let l3 = self.pc;
+        match self.insts.get_mut(l1) {
+            Some(Instruction::CounterCheck(_, _, _, addr)) => *addr = l3,
+            _ => return Err(CodeGenError::FailRepeat),
+        }
+
+        match max {
+            Some(_) => Ok(()),
+            None => self.gen_star(e),
+        }
+    }
+
+    /// save 2n
+    /// codes for e
+    /// save 2n+1
+    fn gen_capture(&mut self, n: usize, e: &AST) -> Result<(), CodeGenError> {
+        self.gen_single_inst(Instruction::Save(2 * n))?;
+        self.gen_expr(e)?;
+        self.gen_single_inst(Instruction::Save(2 * n + 1))?;
+        Ok(())
+    }
+
+    /// L1: lookahead L3
+    /// L2: codes for e
+    ///     match
+    /// L3:
+    fn gen_look(&mut self, look: Instruction, e: &AST) -> Result<(), CodeGenError> {
+        let l1 = self.pc;
+        self.gen_single_inst(look)?;
+
+        self.gen_expr(e)?;
+        self.gen_single_inst(Instruction::Match)?;
+
+        let l3 = self.pc;
+        match self.insts.get_mut(l1) {
+            Some(Instruction::LookAhead(addr))
+            | Some(Instruction::NegLookAhead(addr))
+            | Some(Instruction::LookBehind(_, _, addr))
+            | Some(Instruction::NegLookBehind(_, _, addr))
+            | Some(Instruction::Atomic(addr)) => {
+                *addr = l3;
+            }
+            _ => return Err(CodeGenError::FailLook),
+        }
+
+        Ok(())
+    }
+
     fn gen_seq(&mut self, exprs: &[AST]) -> Result<(), CodeGenError> {
         for e in exprs {
             self.gen_expr(e)?;
@@ -186,6 +365,110 @@ impl Generator {
         self.insts.push(Instruction::Match);
         Ok(())
     }
+
+    /// L1: split L2, L3
+    /// L2: codes for e1
+    ///     match
+    /// L3: split L4, L5
+    /// L4: codes for e2
+    ///     match
+    /// L5: codes for en
+    ///     match
+    fn gen_rules(&mut self, asts: &[AST]) -> Result<Vec<usize>, CodeGenError> {
+        let mut match_pcs = Vec::with_capacity(asts.len());
+        for (i, ast) in asts.iter().enumerate() {
+            let is_last = i + 1 == asts.len();
+            let l1 = self.pc;
+            if !is_last {
+                self.inc_pc()?;
+                let l2 = self.pc;
+                self.insts.push(Instruction::Split(l2, 0));
+            }
+
+            self.gen_expr(ast)?;
+            match_pcs.push(self.pc);
+            self.inc_pc()?;
+            self.insts.push(Instruction::Match);
+
+            if !is_last {
+                match self.insts.get_mut(l1) {
+                    Some(Instruction::Split(_, l3)) => {
+                        *l3 = self.pc;
+                    }
+                    _ => return Err(CodeGenError::FailRules),
+                }
+            }
+        }
+        Ok(match_pcs)
+    }
+}
+
+/// Returns the number of instructions of `e{min,max}` compiled to copies of
+/// `e`.
+fn repeat_len(e: &AST, min: usize, max: Option<usize>) -> Result<usize, CodeGenError> {
+    let mut generator = Generator::default();
+    generator.gen_expr(e)?;
+    let len = generator.insts.len();
+    // each optional copy is preceded by a split, and `e*` adds a split and a
+    // jump
+    let rest = match max {
+        Some(max) => max.saturating_sub(min).saturating_mul(len + 1),
+        None => len + 2,
+    };
+    Ok(min.saturating_mul(len).saturating_add(rest))
+}
+
+/// Returns the minimum and maximum number of chars `ast` can match, or `None`
+/// as the maximum if it is unbounded or unknown.
+fn width(ast: &AST) -> (usize, Option<usize>) {
+    match ast {
+        AST::Char(_) | AST::Class(_) | AST::Period => (1, Some(1)),
+        AST::Caret
+        | AST::Dollar
+        | AST::LookAhead(_)
+        | AST::NegLookAhead(_)
+        | AST::LookBehind(_)
+        | AST::NegLookBehind(_) => (0, Some(0)),
+        AST::Backref(_) => (0, None),
+        AST::Grapheme => (1, None),
+        AST::Plus(e) => match width(e) {
+            (min, Some(0)) => (min, Some(0)),
+            (min, _) => (min, None),
+        },
+        AST::Star(e) => match width(e) {
+            (_, Some(0)) => (0, Some(0)),
+            _ => (0, None),
+        },
+        AST::Question(e) => (0, width(e).1),
+        AST::Repeat(e, min, max) => {
+            let (e_min, e_max) = width(e);
+            let max = match (e_max, max) {
+                (Some(0), _) => Some(0),
+                (Some(e_max), Some(max)) => e_max.checked_mul(*max),
+                _ => None,
+            };
+            (e_min.saturating_mul(*min), max)
+        }
+        AST::Or(e1, e2) => {
+            let (min1, max1) = width(e1);
+            let (min2, max2) = width(e2);
+            (min1.min(min2), max1.zip(max2).map(|(a, b)| a.max(b)))
+        }
+        AST::Seq(seq) => seq.iter().map(width).fold((0, Some(0)), |acc, w| {
+            (
+                acc.0.saturating_add(w.0),
+                acc.1.zip(w.1).and_then(|(a, b)| a.checked_add(b)),
+            )
+        }),
+        AST::Capture(_, e) | AST::Atomic(e) => width(e),
+    }
+}
+
+fn bounded_width(ast: &AST) -> Result<(usize, usize), CodeGenError> {
+    match width(ast) {
+        (min, Some(max)) => Ok((min, max)),
+        (_, None) => Err(CodeGenError::UnboundedLookBehind),
+    }
 }
 
 pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
@@ -193,3 +476,12 @@ pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
     generaotr.gen_code(ast)?;
     Ok(generaotr.insts)
 }
+
+/// Compiles several expressions into one program whose alternatives are tried
+/// in order. Returns the program and the address of the `match` instruction of
+/// each expression.
+pub fn get_rules_code(asts: &[AST]) -> Result<(Vec<Instruction>, Vec<usize>), CodeGenError> {
+    let mut generator = Generator::default();
+    let match_pcs = generator.gen_rules(asts)?;
+    Ok((generator.insts, match_pcs))
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    error::Error,
+    fmt::Display,
+};
+
+use super::codegen::Instruction;
+
+/// upper bound of the number of states built for one program
+const MAX_STATES: usize = 10_000;
+
+/// upper bound of the threads followed while building the states of a
+/// program with counter loops, whose states may have a thread for each count
+const MAX_THREADS: usize = 200_000;
+
+#[derive(Debug)]
+pub enum DfaError {
+    InvalidPC,
+    TooManyStates,
+    /// the instruction cannot be expressed by a finite automaton
+    Unsupported(Instruction),
+}
+
+impl Display for DfaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DfaError::Unsupported(inst) => {
+                write!(f, "DfaError: `{inst}` is unsupported by the DFA")
+            }
+            _ => write!(f, "DfaError: {:?}", self),
+        }
+    }
+}
+
+impl Error for DfaError {}
+
+/// Partition of all chars into classes that no instruction distinguishes.
+#[derive(Debug)]
+pub struct Alphabet {
+    /// first code point of each class in ascending order, starting with 0
+    starts: Vec<u32>,
+}
+
+impl Alphabet {
+    pub fn new(programs: &[&[Instruction]]) -> Alphabet {
+        let mut starts = BTreeSet::from([0]);
+        for inst in programs.iter().flat_map(|p| p.iter()) {
+            match inst {
+                Instruction::Char(c) => {
+                    starts.insert(*c as u32);
+                    starts.insert(after(*c));
+                }
+                Instruction::Class(class) => {
+                    for (lo, hi) in class.ranges() {
+                        starts.insert(*lo as u32);
+                        starts.insert(after(*hi));
+                    }
+                }
+                _ => {}
+            }
+        }
+        starts.retain(|s| *s <= char::MAX as u32);
+        Alphabet {
+            starts: starts.into_iter().collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn class_of(&self, c: char) -> usize {
+        self.starts.partition_point(|s| *s <= c as u32) - 1
+    }
+
+    /// Returns the first and last code points of the class.
+    pub fn range(&self, class: usize) -> (u32, u32) {
+        let hi = match self.starts.get(class + 1) {
+            Some(next) => next - 1,
+            None => char::MAX as u32,
+        };
+        (self.starts[class], hi)
+    }
+
+    /// Returns a char of the class, preferring a readable one.
+    pub fn representative(&self, class: usize) -> char {
+        let (lo, hi) = self.range(class);
+        ('a'..='z')
+            .chain('A'..='Z')
+            .chain('0'..='9')
+            .find(|c| (lo..=hi).contains(&(*c as u32)))
+            .or_else(|| (lo..=hi).find_map(char::from_u32))
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+}
+
+/// Returns the code point following `c`, skipping the surrogates so that no
+/// class consists of them alone.
+fn after(c: char) -> u32 {
+    match c {
+        '\u{D7FF}' => 0xE000,
+        _ => c as u32 + 1,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// accept inputs that contain a match anywhere, like `eval`
+    Search,
+    /// accept inputs matched from the first char to the last
+    Full,
+}
+
+/// address and counters of a thread of the program
+type Thread = (usize, Vec<usize>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StateKey {
+    /// threads to resume, whose counters make the states of a counter loop
+    /// as many as the states of the repetition written out
+    threads: Vec<Thread>,
+    /// no char has been read yet
+    is_head: bool,
+    /// a match has already been found in search mode
+    is_matched: bool,
+}
+
+/// Deterministic automaton built by subset construction. State 0 is the
+/// initial state.
+#[derive(Debug)]
+pub struct Dfa {
+    /// `trans[state][class]` is the next state
+    pub trans: Vec<Vec<usize>>,
+    /// the input read so far is accepted
+    pub accepting: Vec<bool>,
+}
+
+impl Dfa {
+    pub fn new(inst: &[Instruction], alphabet: &Alphabet, mode: Mode) -> Result<Dfa, DfaError> {
+        if let Some(i) = inst.iter().find(|i| {
+            !matches!(
+                i,
+                Instruction::Char(_)
+                    | Instruction::Class(_)
+                    | Instruction::AnyChar
+                    | Instruction::Match
+                    | Instruction::Jump(_)
+                    | Instruction::Split(_, _)
+                    | Instruction::AssertHead
+                    | Instruction::AssertTail
+                    | Instruction::Save(_)
+                    | Instruction::CounterReset(_)
+                    | Instruction::CounterInc(_)
+                    | Instruction::CounterCheck(..)
+            )
+        }) {
+            return Err(DfaError::Unsupported(i.clone()));
+        }
+
+        let init = StateKey {
+            threads: vec![(0, Vec::new())],
+            is_head: true,
+            is_matched: false,
+        };
+        let mut keys = vec![init.clone()];
+        let mut ids = HashMap::from([(init, 0)]);
+        let has_counters = inst
+            .iter()
+            .any(|i| matches!(i, Instruction::CounterReset(_)));
+        let mut budget = if has_counters { MAX_THREADS } else { usize::MAX };
+        let mut dfa = Dfa {
+            trans: Vec::new(),
+            accepting: Vec::new(),
+        };
+
+        while dfa.trans.len() < keys.len() {
+            let key = keys[dfa.trans.len()].clone();
+            let (consuming, is_matched) =
+                closure(inst, &key.threads, key.is_head, false, &mut budget)?;
+            let (_, is_accepting) = closure(inst, &key.threads, key.is_head, true, &mut budget)?;
+            let is_matched = key.is_matched || (mode == Mode::Search && is_matched);
+
+            let mut row = Vec::with_capacity(alphabet.len());
+            for class in 0..alphabet.len() {
+                let next = if is_matched {
+                    StateKey {
+                        threads: Vec::new(),
+                        is_head: false,
+                        is_matched: true,
+                    }
+                } else {
+                    let mut threads = BTreeSet::new();
+                    for (pc, counters) in consuming.iter() {
+                        let is_consumed = match &inst[*pc] {
+                            Instruction::Char(c) => alphabet.class_of(*c) == class,
+                            Instruction::Class(c) => c.contains(alphabet.representative(class)),
+                            Instruction::AnyChar => true,
+                            _ => false,
+                        };
+                        if is_consumed {
+                            threads.insert((pc + 1, counters.clone()));
+                        }
+                    }
+                    if mode == Mode::Search {
+                        threads.insert((0, Vec::new()));
+                    }
+                    StateKey {
+                        threads: threads.into_iter().collect(),
+                        is_head: false,
+                        is_matched: false,
+                    }
+                };
+
+                let id = match ids.get(&next) {
+                    Some(id) => *id,
+                    None => {
+                        if keys.len() >= MAX_STATES {
+                            return Err(DfaError::TooManyStates);
+                        }
+                        ids.insert(next.clone(), keys.len());
+                        keys.push(next);
+                        keys.len() - 1
+                    }
+                };
+                row.push(id);
+            }
+
+            dfa.trans.push(row);
+            dfa.accepting.push(is_matched || is_accepting);
+        }
+
+        Ok(dfa)
+    }
+
+    /// Returns which states can still reach an accepting state.
+    pub fn live_states(&self) -> Vec<bool> {
+        let mut live = self.accepting.clone();
+        let mut is_changed = true;
+        while is_changed {
+            is_changed = false;
+            for state in 0..self.trans.len() {
+                if !live[state] && self.trans[state].iter().any(|next| live[*next]) {
+                    live[state] = true;
+                    is_changed = true;
+                }
+            }
+        }
+        live
+    }
+}
+
+/// Follows the instructions that consume no char. Returns the threads at the
+/// `char`, `class` and `period` instructions reached and whether `match` was
+/// reached. Counters change as in the evaluator. Each thread followed is
+/// taken from `budget`.
+fn closure(
+    inst: &[Instruction],
+    threads: &[Thread],
+    is_head: bool,
+    is_tail: bool,
+    budget: &mut usize,
+) -> Result<(BTreeSet<Thread>, bool), DfaError> {
+    let mut stack = threads.to_vec();
+    let mut visited = BTreeSet::new();
+    let mut consuming = BTreeSet::new();
+    let mut is_matched = false;
+
+    while let Some((pc, mut counters)) = stack.pop() {
+        if !visited.insert((pc, counters.clone())) {
+            continue;
+        }
+        *budget = budget.checked_sub(1).ok_or(DfaError::TooManyStates)?;
+        match inst.get(pc) {
+            Some(Instruction::Char(_))
+            | Some(Instruction::Class(_))
+            | Some(Instruction::AnyChar) => {
+                consuming.insert((pc, counters));
+            }
+            Some(Instruction::Match) => is_matched = true,
+            Some(Instruction::Jump(addr)) => stack.push((*addr, counters)),
+            Some(Instruction::Split(addr1, addr2)) => {
+                stack.push((*addr2, counters.clone()));
+                stack.push((*addr1, counters));
+            }
+            Some(Instruction::AssertHead) => {
+                if is_head {
+                    stack.push((pc + 1, counters));
+                }
+            }
+            Some(Instruction::AssertTail) => {
+                if is_tail {
+                    stack.push((pc + 1, counters));
+                }
+            }
+            Some(Instruction::Save(_)) => stack.push((pc + 1, counters)),
+            Some(Instruction::CounterReset(n)) => {
+                if counters.len() <= *n {
+                    counters.resize(n + 1, 0);
+                }
+                counters[*n] = 0;
+                stack.push((pc + 1, counters));
+            }
+            Some(Instruction::CounterInc(n)) => {
+                if counters.len() <= *n {
+                    counters.resize(n + 1, 0);
+                }
+                counters[*n] += 1;
+                stack.push((pc + 1, counters));
+            }
+            Some(Instruction::CounterCheck(n, min, max, addr)) => {
+                let count = counters.get(*n).copied().unwrap_or(0);
+                // the count is cleared when the loop is left, as in the
+                // evaluator, so that the states after the loop are shared
+                let mut left = counters.clone();
+                if let Some(count) = left.get_mut(*n) {
+                    *count = 0;
+                }
+                if count < *min {
+                    stack.push((pc + 1, counters));
+                } else if count >= *max {
+                    stack.push((*addr, left));
+                } else {
+                    stack.push((*addr, left));
+                    stack.push((pc + 1, counters));
+                }
+            }
+            Some(i) => return Err(DfaError::Unsupported(i.clone())),
+            None => return Err(DfaError::InvalidPC),
+        }
+    }
+
+    Ok((consuming, is_matched))
+}
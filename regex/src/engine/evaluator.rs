@@ -2,37 +2,85 @@ use std::{
     collections::{HashSet, VecDeque},
     error::Error,
     fmt::Display,
+    mem::{swap, take},
 };
 
-use super::codegen::Instruction;
+use super::{
+    cache::{SparseSet, StateSet},
+    codegen::Instruction,
+    unicode,
+};
 
 #[derive(Debug)]
 pub enum EvalError {
     PCOverFlow,
     SPOverFlow,
     InvalidPC,
+    /// the instruction is only supported by the depth-first evaluator
+    DepthFirstOnly(Instruction),
+    /// the instruction cannot be evaluated by approximate matching
+    ApproxUnsupported(Instruction),
+    /// the expression is too long or uses an operator unsupported by the
+    /// bit-parallel evaluator
+    BitParallelUnsupported,
+    /// the instruction reads the input around the string pointer, which is
+    /// not kept when searching a stream
+    Unbuffered(Instruction),
 }
 
 impl Display for EvalError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "EvalError: {:?}", self)
+        match self {
+            EvalError::DepthFirstOnly(inst) => write!(
+                f,
+                "EvalError: `{inst}` is unsupported in this mode, use depth-first evaluation"
+            ),
+            EvalError::ApproxUnsupported(inst) => write!(
+                f,
+                "EvalError: `{inst}` is unsupported in approximate matching"
+            ),
+            EvalError::Unbuffered(inst) => write!(
+                f,
+                "EvalError: `{inst}` needs the whole input in memory"
+            ),
+            _ => write!(f, "EvalError: {:?}", self),
+        }
     }
 }
 
 impl Error for EvalError {}
 
-#[derive(Debug, PartialEq, Eq)]
-struct RegisterContext {
+/// program counter and string pointer, captures and counters
+type VisitedKey = ((usize, usize), Vec<Option<usize>>, Vec<usize>);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegisterContext {
     /// program counter
-    pc: usize,
+    pub pc: usize,
     /// string pointer
-    sp: usize,
+    pub sp: usize,
+    /// capture slots written by `save`
+    pub caps: Vec<Option<usize>>,
+    /// counters of the repetitions compiled to counter loops
+    pub counters: Vec<usize>,
 }
 
 impl RegisterContext {
     #[inline]
-    fn calculate_hash(&self) -> u64 {
-        (self.sp as u64) << 32 | (self.pc as u64)
+    fn state(&self) -> (usize, usize) {
+        (self.pc, self.sp)
+    }
+
+    /// Key of the visited set. Captures are part of the state only when a
+    /// backreference can observe them, while the counters always are.
+    #[inline]
+    fn visited_key(&self, with_caps: bool) -> VisitedKey {
+        let caps = if with_caps {
+            self.caps.clone()
+        } else {
+            Vec::new()
+        };
+        (self.state(), caps, self.counters.clone())
     }
 
     #[inline]
@@ -45,8 +93,8 @@ impl RegisterContext {
     }
 
     #[inline]
-    fn incr_sp(&mut self) -> Result<(), EvalError> {
-        match self.sp.checked_add(1) {
+    fn add_sp(&mut self, n: usize) -> Result<(), EvalError> {
+        match self.sp.checked_add(n) {
             Some(res) => self.sp = res,
             None => return Err(EvalError::SPOverFlow),
         }
@@ -60,47 +108,97 @@ enum MatchStatus {
     Continue(Option<(RegisterContext, RegisterContext)>),
 }
 
+/// Input read by the evaluators. String pointers are char indexes in a line,
+/// but the lockstep evaluator also runs on a stream, where they are byte
+/// offsets and only the char at the string pointer is known.
+pub trait Haystack {
+    /// Returns the char at `sp` and the string pointer after it.
+    fn next_char(&self, sp: usize) -> Option<(char, usize)>;
+
+    /// Returns `true` if `sp` is the end of the input.
+    fn is_end(&self, sp: usize) -> bool;
+
+    /// Returns the whole input, which backreferences, grapheme clusters and
+    /// the sub-programs of lookaround and atomic groups read.
+    fn line(&self) -> Option<&[char]>;
+}
+
+impl Haystack for [char] {
+    #[inline]
+    fn next_char(&self, sp: usize) -> Option<(char, usize)> {
+        self.get(sp).map(|c| (*c, sp + 1))
+    }
+
+    #[inline]
+    fn is_end(&self, sp: usize) -> bool {
+        sp == self.len()
+    }
+
+    #[inline]
+    fn line(&self) -> Option<&[char]> {
+        Some(self)
+    }
+}
+
 impl Instruction {
     #[inline]
-    fn eval_inst<F>(
-        self,
-        line: &[char],
+    fn eval_inst<H, F, G>(
+        &self,
+        input: &H,
         ctx: &mut RegisterContext,
         split_fn: F,
+        mut sub_fn: G,
     ) -> Result<MatchStatus, EvalError>
     where
+        H: Haystack + ?Sized,
         F: FnOnce(RegisterContext, RegisterContext) -> Result<MatchStatus, EvalError>,
+        G: FnMut(RegisterContext, Option<usize>) -> Result<Option<RegisterContext>, EvalError>,
     {
+        let line = || input.line().ok_or_else(|| EvalError::Unbuffered(self.clone()));
         match self {
-            Instruction::Char(c) => match line.get(ctx.sp) {
-                Some(sp_c) => {
-                    if c == *sp_c {
-                        ctx.incr_pc()?;
-                        ctx.incr_sp()?;
-                    } else {
-                        return Ok(MatchStatus::Failed);
-                    }
+            Instruction::Char(c) => match input.next_char(ctx.sp) {
+                Some((sp_c, next)) if *c == sp_c => {
+                    ctx.incr_pc()?;
+                    ctx.sp = next;
                 }
-                None => return Ok(MatchStatus::Failed),
+                _ => return Ok(MatchStatus::Failed),
             },
-            Instruction::AnyChar => match line.get(ctx.sp) {
-                Some(_) => {
+            Instruction::Class(class) => match input.next_char(ctx.sp) {
+                Some((sp_c, next)) if class.contains(sp_c) => {
                     ctx.incr_pc()?;
-                    ctx.incr_sp()?;
+                    ctx.sp = next;
+                }
+                _ => return Ok(MatchStatus::Failed),
+            },
+            Instruction::AnyChar => match input.next_char(ctx.sp) {
+                Some((_, next)) => {
+                    ctx.incr_pc()?;
+                    ctx.sp = next;
                 }
                 None => return Ok(MatchStatus::Failed),
             },
+            Instruction::AnyGrapheme => match unicode::grapheme_len(line()?, ctx.sp) {
+                0 => return Ok(MatchStatus::Failed),
+                len => {
+                    ctx.incr_pc()?;
+                    ctx.add_sp(len)?;
+                }
+            },
             Instruction::Match => return Ok(MatchStatus::Success),
-            Instruction::Jump(addr) => ctx.pc = addr,
+            Instruction::Jump(addr) => ctx.pc = *addr,
             Instruction::Split(addr1, addr2) => {
                 return split_fn(
                     RegisterContext {
-                        pc: addr1,
+                        pc: *addr1,
                         sp: ctx.sp,
+                        caps: ctx.caps.clone(),
+                        counters: ctx.counters.clone(),
                     },
                     RegisterContext {
-                        pc: addr2,
+                        pc: *addr2,
                         sp: ctx.sp,
+                        caps: take(&mut ctx.caps),
+                        counters: take(&mut ctx.counters),
                     },
                 );
             }
@@ -112,83 +210,875 @@ impl Instruction {
                 }
             }
             Instruction::AssertTail => {
-                if ctx.sp == line.len() {
+                if input.is_end(ctx.sp) {
                     ctx.incr_pc()?;
                 } else {
                     return Ok(MatchStatus::Failed);
                 }
             }
+            Instruction::Save(slot) => {
+                if ctx.caps.len() <= *slot {
+                    ctx.caps.resize(slot + 1, None);
+                }
+                ctx.caps[*slot] = Some(ctx.sp);
+                ctx.incr_pc()?;
+            }
+            Instruction::Backref(n) => {
+                let line = line()?;
+                let group = match (ctx.caps.get(2 * n), ctx.caps.get(2 * n + 1)) {
+                    (Some(Some(start)), Some(Some(end))) => line.get(*start..*end),
+                    _ => None,
+                };
+                match group {
+                    Some(group) if line[ctx.sp..].starts_with(group) => {
+                        ctx.incr_pc()?;
+                        ctx.add_sp(group.len())?;
+                    }
+                    _ => return Ok(MatchStatus::Failed),
+                }
+            }
+            Instruction::LookAhead(addr) | Instruction::NegLookAhead(addr) => {
+                let sub_ctx = RegisterContext {
+                    pc: ctx.pc + 1,
+                    sp: ctx.sp,
+                    caps: ctx.caps.clone(),
+                    counters: ctx.counters.clone(),
+                };
+                match (sub_fn(sub_ctx, None)?, self) {
+                    (Some(res), Instruction::LookAhead(_)) => ctx.caps = res.caps,
+                    (None, Instruction::NegLookAhead(_)) => {}
+                    _ => return Ok(MatchStatus::Failed),
+                }
+                ctx.pc = *addr;
+            }
+            Instruction::LookBehind(min, max, addr)
+            | Instruction::NegLookBehind(min, max, addr) => {
+                let mut found = None;
+                for len in *min..=(*max).min(ctx.sp) {
+                    let sub_ctx = RegisterContext {
+                        pc: ctx.pc + 1,
+                        sp: ctx.sp - len,
+                        caps: ctx.caps.clone(),
+                        counters: ctx.counters.clone(),
+                    };
+                    found = sub_fn(sub_ctx, Some(ctx.sp))?;
+                    if found.is_some() {
+                        break;
+                    }
+                }
+                match (found, self) {
+                    (Some(res), Instruction::LookBehind(..)) => ctx.caps = res.caps,
+                    (None, Instruction::NegLookBehind(..)) => {}
+                    _ => return Ok(MatchStatus::Failed),
+                }
+                ctx.pc = *addr;
+            }
+            Instruction::CounterReset(n) => {
+                if ctx.counters.len() <= *n {
+                    ctx.counters.resize(n + 1, 0);
+                }
+                ctx.counters[*n] = 0;
+                ctx.incr_pc()?;
+            }
+            Instruction::CounterInc(n) => {
+                if ctx.counters.len() <= *n {
+                    ctx.counters.resize(n + 1, 0);
+                }
+                ctx.counters[*n] += 1;
+                ctx.incr_pc()?;
+            }
+            Instruction::CounterCheck(n, min, max, addr) => {
+                let count = ctx.counters.get(*n).copied().unwrap_or(0);
+                // the count is dead once the loop is left, and clearing it
+                // lets the threads leaving at different counts share states
+                let leave = |mut counters: Vec<usize>| {
+                    if let Some(count) = counters.get_mut(*n) {
+                        *count = 0;
+                    }
+                    counters
+                };
+                if count < *min {
+                    ctx.incr_pc()?;
+                } else if count >= *max {
+                    ctx.pc = *addr;
+                    ctx.counters = leave(take(&mut ctx.counters));
+                } else {
+                    return split_fn(
+                        RegisterContext {
+                            pc: ctx.pc + 1,
+                            sp: ctx.sp,
+                            caps: ctx.caps.clone(),
+                            counters: ctx.counters.clone(),
+                        },
+                        RegisterContext {
+                            pc: *addr,
+                            sp: ctx.sp,
+                            caps: take(&mut ctx.caps),
+                            counters: leave(take(&mut ctx.counters)),
+                        },
+                    );
+                }
+            }
+            Instruction::Atomic(addr) => {
+                let sub_ctx = RegisterContext {
+                    pc: ctx.pc + 1,
+                    sp: ctx.sp,
+                    caps: take(&mut ctx.caps),
+                    counters: take(&mut ctx.counters),
+                };
+                // the threads left in the sub-program's queue are the
+                // alternatives inside the group, and are dropped here
+                match sub_fn(sub_ctx, None)? {
+                    Some(res) => {
+                        ctx.sp = res.sp;
+                        ctx.caps = res.caps;
+                        ctx.counters = res.counters;
+                    }
+                    None => return Ok(MatchStatus::Failed),
+                }
+                ctx.pc = *addr;
+            }
         }
         Ok(MatchStatus::Continue(None))
     }
 }
 
-#[inline]
+/// Buffers of the evaluator, reused across the start positions of a search
+/// and the sub-programs of lookaround and atomic groups, and across searches
+/// when kept in a `Cache`.
+#[derive(Debug, Clone, Default)]
+pub struct Scratch {
+    /// work done since the scratch was created
+    pub stats: MatchStats,
+    /// the program has backreferences, so captures are part of the state
+    has_backref: bool,
+    /// the program has counters, which are part of the state
+    has_counters: bool,
+    /// buffers of `exact_eval` and `longest_eval`, one per nesting level of
+    /// sub-programs being evaluated
+    frames: Vec<Frame>,
+    /// buffers of `pike_eval`
+    lockstep: Lockstep,
+}
+
+impl Scratch {
+    /// Prepares the scratch for searches with `inst`.
+    fn prepare(&mut self, inst: &[Instruction]) {
+        self.has_backref = inst.iter().any(|i| matches!(i, Instruction::Backref(_)));
+        self.has_counters = inst
+            .iter()
+            .any(|i| matches!(i, Instruction::CounterReset(_)));
+    }
+}
+
+/// Pending threads and visited states of a backtracking search.
+#[derive(Debug, Clone, Default)]
+struct Frame {
+    queue: VecDeque<RegisterContext>,
+    /// visited `(pc, sp)` when neither captures nor counters are part of the
+    /// state
+    states: StateSet,
+    /// visited states otherwise
+    keys: HashSet<VisitedKey>,
+    with_keys: bool,
+    has_backref: bool,
+}
+
+impl Frame {
+    fn reset(
+        &mut self,
+        inst: &[Instruction],
+        line: &[char],
+        scratch: &Scratch,
+    ) -> Result<(), EvalError> {
+        self.queue.clear();
+        self.keys.clear();
+        self.has_backref = scratch.has_backref;
+        self.with_keys = scratch.has_backref || scratch.has_counters;
+        if self.with_keys {
+            self.states.clear();
+            Ok(())
+        } else {
+            self.states.reset(inst.len(), line.len() + 1)
+        }
+    }
+
+    /// Adds the state of `ctx` to the visited set. Returns `false` if it was
+    /// already visited, in which case the thread is pruned.
+    #[inline]
+    fn visit(&mut self, ctx: &RegisterContext, stats: &mut MatchStats) -> bool {
+        let is_new = if self.with_keys {
+            self.keys.insert(ctx.visited_key(self.has_backref))
+        } else {
+            self.states.insert(ctx.pc, ctx.sp)
+        };
+        if is_new {
+            stats.threads_created += 1;
+        } else {
+            stats.threads_pruned += 1;
+        }
+        is_new
+    }
+}
+
+/// Runs the program from `init_reg` until a `match` instruction is reached.
+/// If `end` is given, only a match ending at that position counts.
 fn exact_eval(
     inst: &[Instruction],
     line: &[char],
-    init_sp: usize,
+    init_reg: RegisterContext,
+    end: Option<usize>,
     is_depth: bool,
-) -> Result<bool, EvalError> {
-    let init_reg = RegisterContext { pc: 0, sp: init_sp };
-    let init_reg_hash = init_reg.calculate_hash();
-    let mut ctx_queue = VecDeque::from([init_reg]);
-    let mut ctx_set = HashSet::from([init_reg_hash]);
+    scratch: &mut Scratch,
+) -> Result<Option<RegisterContext>, EvalError> {
+    // the sub-programs evaluated meanwhile take the frames above this one
+    let mut frame = scratch.frames.pop().unwrap_or_default();
+    frame.reset(inst, line, scratch)?;
+    let found = exact_eval_frame(&mut frame, inst, line, init_reg, end, is_depth, scratch)?;
+    scratch.frames.push(frame);
+    Ok(found)
+}
+
+fn exact_eval_frame(
+    frame: &mut Frame,
+    inst: &[Instruction],
+    line: &[char],
+    init_reg: RegisterContext,
+    end: Option<usize>,
+    is_depth: bool,
+    scratch: &mut Scratch,
+) -> Result<Option<RegisterContext>, EvalError> {
+    frame.visit(&init_reg, &mut scratch.stats);
+    frame.queue.push_back(init_reg);
+    scratch.stats.peak_queue_len = scratch.stats.peak_queue_len.max(1);
 
     loop {
-        let mut ctx = match ctx_queue.pop_front() {
+        let mut ctx = match frame.queue.pop_front() {
             Some(it) => it,
-            None => return Ok(false),
+            None => return Ok(None),
         };
+        scratch.stats.instructions += 1;
 
         let status = match inst.get(ctx.pc) {
-            Some(i) => *i,
+            Some(i) => i,
             None => return Err(EvalError::InvalidPC),
         }
-        .eval_inst(line, &mut ctx, |reg1, reg2| {
-            Ok(MatchStatus::Continue(Some((reg1, reg2))))
-        })?;
+        .eval_inst(
+            line,
+            &mut ctx,
+            |reg1, reg2| Ok(MatchStatus::Continue(Some((reg1, reg2)))),
+            |sub_reg, sub_end| exact_eval(inst, line, sub_reg, sub_end, is_depth, scratch),
+        )?;
 
+        let stats = &mut scratch.stats;
         match status {
-            MatchStatus::Success => return Ok(true),
+            MatchStatus::Success => {
+                if end.is_none() || end == Some(ctx.sp) {
+                    return Ok(Some(ctx));
+                }
+            }
             MatchStatus::Failed => {}
             MatchStatus::Continue(it) => match it {
                 Some((ctx1, ctx2)) => {
                     if is_depth {
-                        if ctx_set.insert(ctx2.calculate_hash()) {
-                            ctx_queue.push_front(ctx2);
+                        if frame.visit(&ctx2, stats) {
+                            frame.queue.push_front(ctx2);
                         }
-                        if ctx_set.insert(ctx1.calculate_hash()) {
-                            ctx_queue.push_front(ctx1);
+                        if frame.visit(&ctx1, stats) {
+                            frame.queue.push_front(ctx1);
                         }
                     } else {
-                        if ctx_set.insert(ctx1.calculate_hash()) {
-                            ctx_queue.push_back(ctx1);
+                        if frame.visit(&ctx1, stats) {
+                            frame.queue.push_back(ctx1);
                         }
-                        if ctx_set.insert(ctx2.calculate_hash()) {
-                            ctx_queue.push_back(ctx2);
+                        if frame.visit(&ctx2, stats) {
+                            frame.queue.push_back(ctx2);
                         }
                     }
                 }
                 None => {
-                    if ctx_set.insert(ctx.calculate_hash()) {
+                    if frame.visit(&ctx, stats) {
                         if is_depth {
-                            ctx_queue.push_front(ctx);
+                            frame.queue.push_front(ctx);
                         } else {
-                            ctx_queue.push_back(ctx);
+                            frame.queue.push_back(ctx);
                         }
                     }
                 }
             },
         };
+        stats.peak_queue_len = stats.peak_queue_len.max(frame.queue.len());
     }
 }
 
+/// Returns an error if the program uses an instruction that only the
+/// depth-first evaluator supports.
+pub fn check_breadth_first(inst: &[Instruction]) -> Result<(), EvalError> {
+    match inst
+        .iter()
+        .find(|i| matches!(i, Instruction::Backref(_) | Instruction::Atomic(_)))
+    {
+        Some(i) => Err(EvalError::DepthFirstOnly(i.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Which match is reported among those starting at the leftmost position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchKind {
+    /// the first match in the priority order of the alternatives and greedy
+    /// operators, as in Perl
+    #[default]
+    LeftmostFirst,
+    /// the longest match, as in POSIX
+    LeftmostLongest,
+}
+
+/// Work done by the evaluator to find a match, to compare the evaluation
+/// modes. Lookaround and atomic groups count the work of their sub-programs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchStats {
+    /// instructions evaluated
+    pub instructions: usize,
+    /// threads created in a state not visited before
+    pub threads_created: usize,
+    /// threads dropped because their state was already visited
+    pub threads_pruned: usize,
+    /// largest number of threads waiting to be evaluated at once
+    pub peak_queue_len: usize,
+    /// start positions from which a match was searched
+    pub start_positions: usize,
+}
+
 pub fn eval(inst: &[Instruction], line: &[char], is_depth: bool) -> Result<bool, EvalError> {
-    for (i, _) in line.iter().enumerate() {
-        if exact_eval(inst, line, i, is_depth)? {
-            return Ok(true);
+    eval_with(inst, line, is_depth, &mut Scratch::default())
+}
+
+/// Same as `eval`, but reuses the buffers of `scratch`.
+pub fn eval_with(
+    inst: &[Instruction],
+    line: &[char],
+    is_depth: bool,
+    scratch: &mut Scratch,
+) -> Result<bool, EvalError> {
+    Ok(search(inst, line, is_depth, MatchKind::LeftmostFirst, scratch)?.is_some())
+}
+
+/// Returns the capture slots of the leftmost match. Slots 0 and 1 hold the
+/// bounds of the whole match. The work done is added to the statistics of
+/// `scratch`.
+pub fn eval_captures_with(
+    inst: &[Instruction],
+    line: &[char],
+    is_depth: bool,
+    kind: MatchKind,
+    scratch: &mut Scratch,
+) -> Result<Option<Vec<Option<usize>>>, EvalError> {
+    let (start, ctx) = match search(inst, line, is_depth, kind, scratch)? {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+    let mut caps = ctx.caps;
+    if caps.len() < 2 {
+        caps.resize(2, None);
+    }
+    caps[0] = Some(start);
+    caps[1] = Some(ctx.sp);
+    Ok(Some(caps))
+}
+
+/// Returns the start position of the leftmost match and the registers at its
+/// end.
+fn search(
+    inst: &[Instruction],
+    line: &[char],
+    is_depth: bool,
+    kind: MatchKind,
+    scratch: &mut Scratch,
+) -> Result<Option<(usize, RegisterContext)>, EvalError> {
+    if !is_depth {
+        check_breadth_first(inst)?;
+    }
+    scratch.prepare(inst);
+    // a match may also start at the end of the line, so that expressions
+    // matching the empty string such as `$` or `a*` match there
+    for i in 0..=line.len() {
+        scratch.stats.start_positions += 1;
+        let init_reg = RegisterContext {
+            pc: 0,
+            sp: i,
+            caps: Vec::new(),
+            counters: Vec::new(),
+        };
+        let found = match (is_depth, kind) {
+            (true, MatchKind::LeftmostFirst) => {
+                exact_eval(inst, line, init_reg, None, true, scratch)?
+            }
+            (true, MatchKind::LeftmostLongest) => longest_eval(inst, line, init_reg, scratch)?,
+            (false, _) => pike_eval(inst, line, init_reg, kind, scratch)?,
+        };
+        if let Some(ctx) = found {
+            return Ok(Some((i, ctx)));
         }
     }
-    Ok(false)
+    Ok(None)
+}
+
+/// Returns `true` if the program matches the whole line.
+pub fn eval_full(inst: &[Instruction], line: &[char]) -> Result<bool, EvalError> {
+    let mut scratch = Scratch::default();
+    scratch.prepare(inst);
+    let init_reg = RegisterContext {
+        pc: 0,
+        sp: 0,
+        caps: Vec::new(),
+        counters: Vec::new(),
+    };
+    let found = exact_eval(inst, line, init_reg, Some(line.len()), true, &mut scratch)?;
+    Ok(found.is_some())
+}
+
+/// Returns the address of the `match` instruction reached with the longest
+/// input from `init_sp`, preferring the alternative with the highest priority
+/// on ties, and the string pointer at that point.
+pub fn eval_longest(
+    inst: &[Instruction],
+    line: &[char],
+    init_sp: usize,
+) -> Result<Option<(usize, usize)>, EvalError> {
+    check_breadth_first(inst)?;
+    let mut scratch = Scratch::default();
+    scratch.prepare(inst);
+    let init_reg = RegisterContext {
+        pc: 0,
+        sp: init_sp,
+        caps: Vec::new(),
+        counters: Vec::new(),
+    };
+    let found = pike_eval(
+        inst,
+        line,
+        init_reg,
+        MatchKind::LeftmostLongest,
+        &mut scratch,
+    )?;
+    Ok(found.map(|ctx| (ctx.pc, ctx.sp)))
+}
+
+/// Threads of the lockstep search, shared by `pike_eval` and the streaming
+/// search.
+#[derive(Debug, Clone, Default)]
+pub struct Lockstep {
+    /// threads to be followed at the current string pointer
+    pending: Vec<RegisterContext>,
+    /// threads stopped at an instruction that consumes chars
+    threads: Vec<RegisterContext>,
+    /// threads to be followed at the next string pointer
+    next: Vec<RegisterContext>,
+    /// pending instructions of `add_thread`
+    stack: Vec<RegisterContext>,
+    visited: ThreadSet,
+}
+
+impl Lockstep {
+    /// Removes all threads and prepares the search of `inst`.
+    pub fn reset(&mut self, inst: &[Instruction], has_counters: bool) {
+        self.visited.reset(inst, has_counters);
+        self.pending.clear();
+        self.next.clear();
+    }
+
+    /// Adds a thread with the lowest priority at the next step.
+    pub fn push(&mut self, ctx: RegisterContext) {
+        self.pending.push(ctx);
+    }
+
+    /// Returns `true` if no thread is left.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Keeps only the threads for which `f` returns `true`.
+    pub fn retain<F: FnMut(&RegisterContext) -> bool>(&mut self, f: F) {
+        self.pending.retain(f);
+    }
+
+    /// Follows the threads at `sp` to the instructions that consume chars,
+    /// then advances them over the char at `sp` in priority order. The first
+    /// thread reaching `match` replaces `matched`, since it is longer than a
+    /// match found at an earlier string pointer. With leftmost-first
+    /// semantics the threads with a lower priority are dropped. Returns
+    /// `true` if a match was found.
+    ///
+    /// A thread that consumed a grapheme cluster of several chars keeps its
+    /// place in the order and waits until the others reach its string
+    /// pointer.
+    pub fn step<H: Haystack + ?Sized>(
+        &mut self,
+        inst: &[Instruction],
+        input: &H,
+        sp: usize,
+        kind: MatchKind,
+        matched: &mut Option<RegisterContext>,
+        scratch: &mut Scratch,
+    ) -> Result<bool, EvalError> {
+        self.threads.clear();
+        self.visited.clear();
+        let mut pending = take(&mut self.pending);
+        for ctx in pending.drain(..) {
+            if ctx.sp == sp {
+                self.add_thread(inst, input, ctx, scratch)?;
+            } else {
+                self.threads.push(ctx);
+            }
+        }
+        self.pending = pending;
+        let stats = &mut scratch.stats;
+        stats.peak_queue_len = stats.peak_queue_len.max(self.threads.len());
+
+        let mut found = false;
+        let mut threads = take(&mut self.threads);
+        for mut ctx in threads.drain(..) {
+            if ctx.sp != sp {
+                self.next.push(ctx);
+                continue;
+            }
+            let i = &inst[ctx.pc];
+            scratch.stats.instructions += 1;
+            if let Instruction::Match = i {
+                if !found {
+                    *matched = Some(ctx);
+                    found = true;
+                }
+                match kind {
+                    MatchKind::LeftmostFirst => break,
+                    MatchKind::LeftmostLongest => continue,
+                }
+            }
+            let status = i.eval_inst(
+                input,
+                &mut ctx,
+                |reg1, reg2| Ok(MatchStatus::Continue(Some((reg1, reg2)))),
+                |sub_reg, sub_end| sub_eval(inst, input, i, sub_reg, sub_end, scratch),
+            )?;
+            if let MatchStatus::Continue(None) = status {
+                self.next.push(ctx);
+            }
+        }
+        self.threads = threads;
+
+        swap(&mut self.pending, &mut self.next);
+        Ok(found)
+    }
+
+    /// Follows the instructions that consume no char from `ctx` in priority
+    /// order, and appends the threads stopped at an instruction that
+    /// consumes chars or at a `match` instruction.
+    fn add_thread<H: Haystack + ?Sized>(
+        &mut self,
+        inst: &[Instruction],
+        input: &H,
+        ctx: RegisterContext,
+        scratch: &mut Scratch,
+    ) -> Result<(), EvalError> {
+        self.stack.clear();
+        self.stack.push(ctx);
+        while let Some(mut ctx) = self.stack.pop() {
+            let i = match inst.get(ctx.pc) {
+                Some(i) => i,
+                None => return Err(EvalError::InvalidPC),
+            };
+            if !self.visited.insert(&ctx) {
+                scratch.stats.threads_pruned += 1;
+                continue;
+            }
+            scratch.stats.threads_created += 1;
+
+            if let Instruction::Char(_)
+            | Instruction::Class(_)
+            | Instruction::AnyChar
+            | Instruction::AnyGrapheme
+            | Instruction::Match = i
+            {
+                self.threads.push(ctx);
+                continue;
+            }
+            scratch.stats.instructions += 1;
+            let status = i.eval_inst(
+                input,
+                &mut ctx,
+                |reg1, reg2| Ok(MatchStatus::Continue(Some((reg1, reg2)))),
+                |sub_reg, sub_end| sub_eval(inst, input, i, sub_reg, sub_end, scratch),
+            )?;
+            match status {
+                MatchStatus::Continue(Some((ctx1, ctx2))) => {
+                    self.stack.push(ctx2);
+                    self.stack.push(ctx1);
+                }
+                MatchStatus::Continue(None) => self.stack.push(ctx),
+                MatchStatus::Success | MatchStatus::Failed => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs the sub-program of the lookaround or atomic group `i` breadth first
+/// on the whole input.
+fn sub_eval<H: Haystack + ?Sized>(
+    inst: &[Instruction],
+    input: &H,
+    i: &Instruction,
+    sub_reg: RegisterContext,
+    sub_end: Option<usize>,
+    scratch: &mut Scratch,
+) -> Result<Option<RegisterContext>, EvalError> {
+    match input.line() {
+        Some(line) => exact_eval(inst, line, sub_reg, sub_end, false, scratch),
+        None => Err(EvalError::Unbuffered(i.clone())),
+    }
+}
+
+/// Runs all threads in lockstep over the input, keeping them in priority
+/// order, so that the captures are those of the preferred match. With
+/// leftmost-first semantics the threads with a lower priority than a matched
+/// one are dropped. With leftmost-longest semantics the evaluation continues
+/// after a match until no thread is left, and a later match replaces an
+/// earlier one since it is longer.
+fn pike_eval(
+    inst: &[Instruction],
+    line: &[char],
+    init_reg: RegisterContext,
+    kind: MatchKind,
+    scratch: &mut Scratch,
+) -> Result<Option<RegisterContext>, EvalError> {
+    let mut lockstep = take(&mut scratch.lockstep);
+    lockstep.reset(inst, scratch.has_counters);
+    let mut matched = None;
+    let mut sp = init_reg.sp;
+    lockstep.push(init_reg);
+
+    while !lockstep.is_empty() {
+        lockstep.step(inst, line, sp, kind, &mut matched, scratch)?;
+        sp += 1;
+    }
+
+    scratch.lockstep = lockstep;
+    Ok(matched)
+}
+
+/// Threads added at one string pointer, identified by their program counter,
+/// and also by their counters if the program has any.
+#[derive(Debug, Clone, Default)]
+struct ThreadSet {
+    pcs: SparseSet,
+    states: HashSet<(usize, Vec<usize>)>,
+    has_counters: bool,
+}
+
+impl ThreadSet {
+    fn reset(&mut self, inst: &[Instruction], has_counters: bool) {
+        self.pcs.reset(inst.len());
+        self.states.clear();
+        self.has_counters = has_counters;
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        self.states.clear();
+    }
+
+    /// Returns `true` if no thread in the same state was added.
+    fn insert(&mut self, ctx: &RegisterContext) -> bool {
+        if self.has_counters {
+            self.states.insert((ctx.pc, ctx.counters.clone()))
+        } else {
+            self.pcs.insert(ctx.pc)
+        }
+    }
+}
+
+/// Explores every state reachable from `init_reg` depth first instead of
+/// stopping at the first `match`. Among the paths to the longest match the
+/// one found first, which has the highest priority, sets the captures.
+fn longest_eval(
+    inst: &[Instruction],
+    line: &[char],
+    init_reg: RegisterContext,
+    scratch: &mut Scratch,
+) -> Result<Option<RegisterContext>, EvalError> {
+    let mut frame = scratch.frames.pop().unwrap_or_default();
+    frame.reset(inst, line, scratch)?;
+    frame.visit(&init_reg, &mut scratch.stats);
+    frame.queue.push_back(init_reg);
+    let mut longest: Option<RegisterContext> = None;
+    scratch.stats.peak_queue_len = scratch.stats.peak_queue_len.max(1);
+
+    while let Some(mut ctx) = frame.queue.pop_back() {
+        scratch.stats.instructions += 1;
+        let status = match inst.get(ctx.pc) {
+            Some(i) => i,
+            None => return Err(EvalError::InvalidPC),
+        }
+        .eval_inst(
+            line,
+            &mut ctx,
+            |reg1, reg2| Ok(MatchStatus::Continue(Some((reg1, reg2)))),
+            |sub_reg, sub_end| exact_eval(inst, line, sub_reg, sub_end, true, scratch),
+        )?;
+
+        match status {
+            MatchStatus::Success => {
+                let is_longer = match &longest {
+                    Some(l) => ctx.sp > l.sp,
+                    None => true,
+                };
+                if is_longer {
+                    longest = Some(ctx);
+                }
+            }
+            MatchStatus::Failed => {}
+            MatchStatus::Continue(Some((ctx1, ctx2))) => {
+                for ctx in [ctx2, ctx1] {
+                    if frame.visit(&ctx, &mut scratch.stats) {
+                        frame.queue.push_back(ctx);
+                    }
+                }
+            }
+            MatchStatus::Continue(None) => {
+                if frame.visit(&ctx, &mut scratch.stats) {
+                    frame.queue.push_back(ctx);
+                }
+            }
+        }
+    }
+
+    scratch.frames.push(frame);
+    Ok(longest)
+}
+
+/// Approximate match found by `eval_approx`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ApproxMatch {
+    pub start: usize,
+    pub end: usize,
+    /// number of inserted, deleted and substituted chars
+    pub cost: usize,
+}
+
+/// Finds the match with the fewest edits, at most `max_cost`, preferring the
+/// leftmost one on ties. A `char` or `period` instruction may also
+/// - substitute the current char (pc + 1, sp + 1),
+/// - skip itself as if the char were deleted from the input (pc + 1),
+///
+/// and any instruction but `match` may skip the current char as if it were
+/// inserted into the input (sp + 1), each at a cost of one.
+pub fn eval_approx(
+    inst: &[Instruction],
+    line: &[char],
+    max_cost: usize,
+) -> Result<Option<ApproxMatch>, EvalError> {
+    if let Some(i) = inst.iter().find(|i| matches!(i, Instruction::Backref(_))) {
+        return Err(EvalError::ApproxUnsupported(i.clone()));
+    }
+
+    let mut scratch = Scratch::default();
+    scratch.prepare(inst);
+    let mut best: Option<ApproxMatch> = None;
+    for i in 0..=line.len() {
+        let limit = match &best {
+            Some(m) if m.cost == 0 => break,
+            Some(m) => m.cost - 1,
+            None => max_cost,
+        };
+        if let Some(m) = approx_eval(inst, line, i, limit, &mut scratch)? {
+            best = Some(m);
+        }
+    }
+    Ok(best)
+}
+
+/// Searches the states in order of cost. `buckets[c]` holds the states
+/// reached with `c` edits, and grows only as far as edits are made, since
+/// `max_cost` may be far above any cost the search can reach.
+fn approx_eval(
+    inst: &[Instruction],
+    line: &[char],
+    init_sp: usize,
+    max_cost: usize,
+    scratch: &mut Scratch,
+) -> Result<Option<ApproxMatch>, EvalError> {
+    let mut buckets = vec![Vec::new()];
+    buckets[0].push(RegisterContext {
+        pc: 0,
+        sp: init_sp,
+        caps: Vec::new(),
+        counters: Vec::new(),
+    });
+    let mut ctx_set = HashSet::new();
+
+    let mut cost = 0;
+    while cost < buckets.len() {
+        while let Some(mut ctx) = buckets[cost].pop() {
+            if !ctx_set.insert((ctx.state(), ctx.counters.clone())) {
+                continue;
+            }
+
+            let i = match inst.get(ctx.pc) {
+                Some(i) => i,
+                None => return Err(EvalError::InvalidPC),
+            };
+            if cost < max_cost {
+                let edit = |pc, sp| RegisterContext {
+                    pc,
+                    sp,
+                    caps: ctx.caps.clone(),
+                    counters: ctx.counters.clone(),
+                };
+                if buckets.len() == cost + 1 {
+                    buckets.push(Vec::new());
+                }
+                // an inserted char can be skipped in any state, such as
+                // before `$`
+                if ctx.sp < line.len() && !matches!(i, Instruction::Match) {
+                    buckets[cost + 1].push(edit(ctx.pc, ctx.sp + 1));
+                }
+                if let Instruction::Char(_)
+                | Instruction::Class(_)
+                | Instruction::AnyChar
+                | Instruction::AnyGrapheme = i
+                {
+                    buckets[cost + 1].push(edit(ctx.pc + 1, ctx.sp));
+                    if ctx.sp < line.len() {
+                        buckets[cost + 1].push(edit(ctx.pc + 1, ctx.sp + 1));
+                    }
+                }
+            }
+
+            let status = i.eval_inst(
+                line,
+                &mut ctx,
+                |reg1, reg2| Ok(MatchStatus::Continue(Some((reg1, reg2)))),
+                |sub_reg, sub_end| exact_eval(inst, line, sub_reg, sub_end, true, scratch),
+            )?;
+            match status {
+                MatchStatus::Success => {
+                    return Ok(Some(ApproxMatch {
+                        start: init_sp,
+                        end: ctx.sp,
+                        cost,
+                    }))
+                }
+                MatchStatus::Failed => {}
+                MatchStatus::Continue(Some((ctx1, ctx2))) => {
+                    buckets[cost].push(ctx2);
+                    buckets[cost].push(ctx1);
+                }
+                MatchStatus::Continue(None) => buckets[cost].push(ctx),
+            }
+        }
+        cost += 1;
+    }
+
+    Ok(None)
 }
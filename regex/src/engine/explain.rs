@@ -0,0 +1,206 @@
+//! Description of an expression in English, one operator per line and the
+//! operands indented below it, for reading dense patterns in reviews.
+//!
+//! Patterns in another syntax or with other options are described through
+//! [`RegexBuilder::explain`](super::RegexBuilder::explain).
+//!
+//! ```text
+//! $ cargo run -p regex --features playground --bin playground -- --explain '^(ab|cd)+x?$'
+//! sequence of:
+//!   start of line
+//!   one or more of group 1, one of:
+//!     "ab"
+//!     "cd"
+//!   optionally 'x'
+//!   end of line
+//! ```
+
+use super::{
+    class::CharClass,
+    parser::{self, ParseError, AST},
+};
+
+/// Parses `expr` with the default options and describes it, one line per
+/// operator.
+pub fn explain(expr: &str) -> Result<String, ParseError> {
+    let ast = parser::parse(expr)?;
+    Ok(explain_ast(&ast))
+}
+
+/// Describes a parsed expression, one line per operator.
+pub(super) fn explain_ast(ast: &AST) -> String {
+    describe(ast).into_iter().map(|line| line + "\n").collect()
+}
+
+/// Returns the lines describing `ast`, indented relative to it.
+fn describe(ast: &AST) -> Vec<String> {
+    match ast {
+        AST::Char(c) => vec![format!("'{}'", c.escape_debug())],
+        AST::Class(class) => vec![describe_class(class)],
+        AST::Period => vec!["any char".to_string()],
+        AST::Grapheme => vec!["any grapheme cluster".to_string()],
+        AST::Caret => vec!["start of line".to_string()],
+        AST::Dollar => vec!["end of line".to_string()],
+        AST::Backref(n) => vec![format!("the text matched by group {n}")],
+        AST::Seq(seq) => describe_seq(seq),
+        AST::Or(..) => {
+            let mut alternatives = Vec::new();
+            collect_alternatives(ast, &mut alternatives);
+            let mut lines = vec!["one of:".to_string()];
+            for e in alternatives {
+                lines.extend(indent(describe(e)));
+            }
+            lines
+        }
+        AST::Star(e) => unary("zero or more of", e),
+        AST::Plus(e) => unary("one or more of", e),
+        AST::Question(e) => unary("optionally", e),
+        AST::Repeat(e, min, max) => {
+            let label = match max {
+                Some(max) if max == min => format!("exactly {min} of"),
+                Some(max) => format!("between {min} and {max} of"),
+                None => format!("at least {min} of"),
+            };
+            unary(&label, e)
+        }
+        AST::Capture(n, e) => unary(&format!("group {n},"), e),
+        AST::LookAhead(e) => unary("followed by", e),
+        AST::NegLookAhead(e) => unary("not followed by", e),
+        AST::LookBehind(e) => unary("preceded by", e),
+        AST::NegLookBehind(e) => unary("not preceded by", e),
+        AST::Atomic(e) => unary("atomically,", e),
+    }
+}
+
+/// Describes an operator on the first line of its operand, so that the
+/// operators applied to one operand read as a single phrase.
+fn unary(label: &str, e: &AST) -> Vec<String> {
+    let mut lines = describe(e);
+    lines[0] = format!("{label} {}", lines[0]);
+    lines
+}
+
+/// Describes a sequence with its runs of chars joined into strings.
+fn describe_seq(seq: &[AST]) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut literal = String::new();
+    for e in seq {
+        match e {
+            AST::Char(c) => literal.push(*c),
+            _ => {
+                push_literal(&mut items, &mut literal);
+                items.push(describe(e));
+            }
+        }
+    }
+    push_literal(&mut items, &mut literal);
+
+    match items.len() {
+        0 => return vec!["empty string".to_string()],
+        1 => return items.pop().unwrap(),
+        _ => {}
+    }
+    let mut lines = vec!["sequence of:".to_string()];
+    for item in items {
+        lines.extend(indent(item));
+    }
+    lines
+}
+
+fn push_literal(items: &mut Vec<Vec<String>>, literal: &mut String) {
+    match literal.chars().count() {
+        0 => {}
+        1 => items.push(vec![format!("'{}'", literal.escape_debug())]),
+        _ => items.push(vec![format!("\"{}\"", literal.escape_debug())]),
+    }
+    literal.clear();
+}
+
+/// Flattens nested alternations, which the parser builds right-nested.
+fn collect_alternatives<'a>(ast: &'a AST, alternatives: &mut Vec<&'a AST>) {
+    match ast {
+        AST::Or(e1, e2) => {
+            collect_alternatives(e1, alternatives);
+            collect_alternatives(e2, alternatives);
+        }
+        e => alternatives.push(e),
+    }
+}
+
+/// Describes a class by its chars, or by the chars it excludes if it was
+/// negated.
+fn describe_class(class: &CharClass) -> String {
+    if class.contains('\0') && class.contains(char::MAX) {
+        format!("any char except {}", class.negate())
+    } else {
+        format!("any char in {class}")
+    }
+}
+
+fn indent(lines: Vec<String>) -> impl Iterator<Item = String> {
+    lines.into_iter().map(|line| format!("  {line}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::explain;
+    use crate::engine::{RegexBuilder, Syntax};
+    use rstest::*;
+
+    #[rstest]
+    #[case("abc", "\"abc\"\n")]
+    #[case("a", "'a'\n")]
+    #[case("a*", "zero or more of 'a'\n")]
+    #[case("(ab|cd)+", "one or more of group 1, one of:\n  \"ab\"\n  \"cd\"\n")]
+    #[case(
+        "^(ab|cd)+x?$",
+        "sequence of:\n  start of line\n  one or more of group 1, one of:\n    \"ab\"\n    \"cd\"\n  optionally 'x'\n  end of line\n"
+    )]
+    #[case("a|b|c", "one of:\n  'a'\n  'b'\n  'c'\n")]
+    #[case("x{2,4}", "between 2 and 4 of 'x'\n")]
+    #[case("x{3}", "exactly 3 of 'x'\n")]
+    #[case("(xy){2,}", "at least 2 of group 1, \"xy\"\n")]
+    #[case("[a-c]", "any char in [a-c]\n")]
+    #[case("[^a-c]", "any char except [a-c]\n")]
+    #[case(
+        "(a)(?<=b.)\\1",
+        "sequence of:\n  group 1, 'a'\n  preceded by sequence of:\n    'b'\n    any char\n  the text matched by group 1\n"
+    )]
+    #[case(
+        "(?>a|ab)(?!c)",
+        "sequence of:\n  atomically, one of:\n    'a'\n    \"ab\"\n  not followed by 'c'\n"
+    )]
+    #[case("\\.\\*", "\".*\"\n")]
+    #[case("()", "group 1, empty string\n")]
+    fn test_explain(#[case] expr: &str, #[case] expected: &str) {
+        assert_eq!(explain(expr).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(Syntax::PosixBasic, "a\\{2\\}", "exactly 2 of 'a'\n")]
+    #[case(Syntax::PosixBasic, "a{2}|b", "\"a{2}|b\"\n")]
+    #[case(Syntax::PosixBasic, "\\(ab\\)*", "zero or more of group 1, \"ab\"\n")]
+    #[case(
+        Syntax::PosixExtended,
+        "(a|b)+",
+        "one or more of group 1, one of:\n  'a'\n  'b'\n"
+    )]
+    fn test_explain_syntax(#[case] syntax: Syntax, #[case] expr: &str, #[case] expected: &str) {
+        let builder = RegexBuilder::new(expr).syntax(syntax);
+        assert_eq!(builder.explain().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_explain_options() {
+        let builder = RegexBuilder::new("a . # any").verbose(true).grapheme(true);
+        assert_eq!(
+            builder.explain().unwrap(),
+            "sequence of:\n  'a'\n  any grapheme cluster\n"
+        );
+    }
+
+    #[test]
+    fn test_explain_error() {
+        assert!(explain("(ab").is_err());
+    }
+}
@@ -0,0 +1,558 @@
+//! Differential testing of the evaluators. Random expressions are generated
+//! over the `AST` grammar and printed as patterns, and random lines are drawn
+//! from a small alphabet so that they often match. Every engine must agree
+//! with a reference backtracker that interprets the `AST` directly, and a
+//! disagreement is shrunk to a minimal pattern and line before it is
+//! reported.
+//!
+//! Backreferences and grapheme clusters are not generated, since the
+//! reference does not track captures or segment graphemes. Some repetitions
+//! get a maximum count far beyond the lines, so that they are compiled to
+//! counter loops, while their minimum stays small to keep the reference fast.
+
+use std::fmt::Display;
+
+use super::{
+    class::CharClass, jit::JitRegex, parser::AST, sample::XorShift, Cache, Engine, EvalError,
+    MatchKind, Regex, RegexError,
+};
+
+/// chars of the generated expressions and lines
+const ALPHABET: &[char] = &['a', 'b', 'c', 'd'];
+
+/// An engine under test, returning whether the line matches.
+type EngineFn = fn(&Regex, &str) -> Result<bool, RegexError>;
+
+/// engines compared with the reference
+const ENGINES: &[(&str, EngineFn)] = &[
+    ("depth-first", |regex, line| {
+        regex.is_match_engine(line, Engine::DepthFirst, &mut Cache::new())
+    }),
+    ("breadth-first", |regex, line| {
+        regex.is_match_engine(line, Engine::BreadthFirst, &mut Cache::new())
+    }),
+    ("bit-parallel", |regex, line| {
+        regex.is_match_engine(line, Engine::BitParallel, &mut Cache::new())
+    }),
+    ("captures depth-first", |regex, line| {
+        Ok(regex.captures(line, true)?.is_some())
+    }),
+    ("captures breadth-first", |regex, line| {
+        Ok(regex.captures(line, false)?.is_some())
+    }),
+    ("leftmost-longest depth-first", |regex, line| {
+        let mut regex = regex.clone();
+        regex.match_kind = MatchKind::LeftmostLongest;
+        Ok(regex.captures(line, true)?.is_some())
+    }),
+    ("leftmost-longest breadth-first", |regex, line| {
+        let mut regex = regex.clone();
+        regex.match_kind = MatchKind::LeftmostLongest;
+        Ok(regex.captures(line, false)?.is_some())
+    }),
+    ("jit", |regex, line| JitRegex::new(regex).is_match(line)),
+];
+
+/// A pattern and a line on which an engine disagrees with the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub pattern: String,
+    pub line: String,
+    /// result of the reference backtracker
+    pub expected: bool,
+    /// engines that disagree, with their result or error
+    pub engines: Vec<(&'static str, Result<bool, String>)>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pattern {:?} on line {:?}: expected {}",
+            self.pattern, self.line, self.expected
+        )?;
+        for (name, res) in self.engines.iter() {
+            match res {
+                Ok(is_match) => write!(f, ", {name} returned {is_match}")?,
+                Err(e) => write!(f, ", {name} failed with {e}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Generates random expressions and lines and compares the engines on them.
+pub struct Fuzzer {
+    rng: XorShift,
+    max_depth: usize,
+    max_len: usize,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Fuzzer {
+        Fuzzer {
+            rng: XorShift::new(seed),
+            max_depth: 4,
+            max_len: 8,
+        }
+    }
+
+    /// Checks `cases` random expressions, each on a few random lines, and
+    /// returns the first mismatch after shrinking it.
+    pub fn run(&mut self, cases: usize) -> Result<(), Mismatch> {
+        for _ in 0..cases {
+            let ast = self.gen_ast(self.max_depth);
+            for _ in 0..4 {
+                let line = self.gen_line();
+                if check(&ast, &line).is_err() {
+                    let (ast, line) = shrink(ast, line, |ast, line| check(ast, line).is_err());
+                    return check(&ast, &line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn gen_line(&mut self) -> String {
+        let len = self.rng.below(self.max_len + 1);
+        (0..len)
+            .map(|_| ALPHABET[self.rng.below(ALPHABET.len())])
+            .collect()
+    }
+
+    fn gen_sub(&mut self, depth: usize) -> Box<AST> {
+        Box::new(self.gen_ast(depth - 1))
+    }
+
+    fn gen_ast(&mut self, depth: usize) -> AST {
+        let choice = if depth == 0 {
+            self.rng.below(5)
+        } else {
+            self.rng.below(17)
+        };
+        match choice {
+            0 | 1 => AST::Char(ALPHABET[self.rng.below(ALPHABET.len())]),
+            2 => {
+                // a non-empty subset of the alphabet, since `[]` and `[^]`
+                // cannot be written
+                let mut ranges = ALPHABET
+                    .iter()
+                    .filter(|_| self.rng.below(2) == 0)
+                    .map(|c| (*c, *c))
+                    .collect::<Vec<_>>();
+                if ranges.is_empty() {
+                    let c = ALPHABET[self.rng.below(ALPHABET.len())];
+                    ranges.push((c, c));
+                }
+                let class = CharClass::new(ranges);
+                match self.rng.below(2) {
+                    0 => AST::Class(class.negate()),
+                    _ => AST::Class(class),
+                }
+            }
+            3 => AST::Period,
+            4 => match self.rng.below(4) {
+                0 => AST::Caret,
+                1 => AST::Dollar,
+                _ => AST::Char(ALPHABET[0]),
+            },
+            5 => AST::Star(self.gen_sub(depth)),
+            6 => AST::Plus(self.gen_sub(depth)),
+            7 => AST::Question(self.gen_sub(depth)),
+            8 | 9 => AST::Or(self.gen_sub(depth), self.gen_sub(depth)),
+            10..=12 => {
+                let len = 2 + self.rng.below(3);
+                AST::Seq((0..len).map(|_| self.gen_ast(depth - 1)).collect())
+            }
+            13 => {
+                let min = self.rng.below(3);
+                let max = match self.rng.below(6) {
+                    0 => None,
+                    1 => Some(min + 600 + self.rng.below(600)),
+                    _ => Some(min + self.rng.below(3)),
+                };
+                AST::Repeat(self.gen_sub(depth), min, max)
+            }
+            14 => AST::Capture(1, self.gen_sub(depth)),
+            15 => match self.rng.below(4) {
+                0 => AST::LookAhead(self.gen_sub(depth)),
+                1 => AST::NegLookAhead(self.gen_sub(depth)),
+                2 => AST::LookBehind(self.gen_sub(depth)),
+                _ => AST::NegLookBehind(self.gen_sub(depth)),
+            },
+            _ => AST::Atomic(self.gen_sub(depth)),
+        }
+    }
+}
+
+/// Compares every engine with the reference on one expression and line.
+/// Expressions that do not compile, such as unbounded lookbehind, and
+/// engines that do not support an expression are skipped.
+pub fn check(ast: &AST, line: &str) -> Result<(), Mismatch> {
+    let pattern = to_pattern(ast);
+    let regex = match Regex::new(&pattern) {
+        Ok(regex) => regex,
+        Err(_) => return Ok(()),
+    };
+    let chars = line.chars().collect::<Vec<_>>();
+    let expected = (0..=chars.len()).any(|i| reference(ast, &chars, i, &mut |_| true));
+
+    let engines = ENGINES
+        .iter()
+        .filter_map(|(name, engine)| match engine(&regex, line) {
+            Ok(is_match) if is_match == expected => None,
+            Ok(is_match) => Some((*name, Ok(is_match))),
+            Err(RegexError::Eval(
+                EvalError::DepthFirstOnly(_) | EvalError::BitParallelUnsupported,
+            )) => None,
+            Err(e) => Some((*name, Err(e.to_string()))),
+        })
+        .collect::<Vec<_>>();
+    if engines.is_empty() {
+        Ok(())
+    } else {
+        Err(Mismatch {
+            pattern,
+            line: line.to_string(),
+            expected,
+            engines,
+        })
+    }
+}
+
+/// Returns `true` if `ast` matches from `i` with an end position accepted by
+/// the continuation `k`, trying the alternatives in priority order.
+fn reference(ast: &AST, line: &[char], i: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    match ast {
+        AST::Char(c) => line.get(i) == Some(c) && k(i + 1),
+        AST::Class(class) => line.get(i).is_some_and(|c| class.contains(*c)) && k(i + 1),
+        AST::Period => i < line.len() && k(i + 1),
+        AST::Caret => i == 0 && k(i),
+        AST::Dollar => i == line.len() && k(i),
+        AST::Seq(seq) => reference_seq(seq, line, i, k),
+        AST::Or(e1, e2) => {
+            if reference(e1, line, i, k) {
+                return true;
+            }
+            reference(e2, line, i, k)
+        }
+        AST::Star(e) => reference_repeat(e, 0, None, line, i, k),
+        AST::Plus(e) => reference_repeat(e, 1, None, line, i, k),
+        AST::Question(e) => reference_repeat(e, 0, Some(1), line, i, k),
+        AST::Repeat(e, min, max) => reference_repeat(e, *min, *max, line, i, k),
+        AST::Capture(_, e) => reference(e, line, i, k),
+        AST::LookAhead(e) => reference(e, line, i, &mut |_| true) && k(i),
+        AST::NegLookAhead(e) => !reference(e, line, i, &mut |_| true) && k(i),
+        AST::LookBehind(e) => (0..=i).any(|s| reference(e, line, s, &mut |j| j == i)) && k(i),
+        AST::NegLookBehind(e) => !(0..=i).any(|s| reference(e, line, s, &mut |j| j == i)) && k(i),
+        AST::Atomic(e) => {
+            let mut first = None;
+            reference(e, line, i, &mut |j| {
+                first = Some(j);
+                true
+            });
+            first.is_some_and(k)
+        }
+        AST::Backref(_) | AST::Grapheme => unreachable!("not generated"),
+    }
+}
+
+fn reference_seq(seq: &[AST], line: &[char], i: usize, k: &mut dyn FnMut(usize) -> bool) -> bool {
+    match seq.split_first() {
+        Some((first, rest)) => reference(first, line, i, &mut |j| reference_seq(rest, line, j, k)),
+        None => k(i),
+    }
+}
+
+/// Greedy repetition. An optional iteration matching the empty string ends
+/// the loop, as the visited states do in the evaluators.
+fn reference_repeat(
+    e: &AST,
+    min: usize,
+    max: Option<usize>,
+    line: &[char],
+    i: usize,
+    k: &mut dyn FnMut(usize) -> bool,
+) -> bool {
+    if min > 0 {
+        return reference(e, line, i, &mut |j| {
+            reference_repeat(e, min - 1, max.map(|m| m - 1), line, j, k)
+        });
+    }
+    if max != Some(0)
+        && reference(e, line, i, &mut |j| {
+            j != i && reference_repeat(e, 0, max.map(|m| m - 1), line, j, k)
+        })
+    {
+        return true;
+    }
+    k(i)
+}
+
+/// Prints an expression as a pattern that parses to an equivalent one.
+/// Operands are grouped with capture groups, which do not change whether a
+/// line matches.
+pub fn to_pattern(ast: &AST) -> String {
+    match ast {
+        AST::Char(c) => {
+            if "\\()[]{}|*+?.^$".contains(*c) {
+                format!("\\{c}")
+            } else {
+                c.to_string()
+            }
+        }
+        AST::Class(class) => {
+            let (negated, class) = if class.contains('\0') {
+                ("^", class.negate())
+            } else {
+                ("", class.clone())
+            };
+            let ranges = class
+                .ranges()
+                .iter()
+                .map(|(lo, hi)| match lo == hi {
+                    true => lo.to_string(),
+                    false => format!("{lo}-{hi}"),
+                })
+                .collect::<String>();
+            format!("[{negated}{ranges}]")
+        }
+        AST::Period => ".".to_string(),
+        AST::Grapheme => ".".to_string(),
+        AST::Caret => "^".to_string(),
+        AST::Dollar => "$".to_string(),
+        AST::Seq(seq) => seq
+            .iter()
+            .map(|e| match e {
+                AST::Or(..) => format!("({})", to_pattern(e)),
+                _ => to_pattern(e),
+            })
+            .collect(),
+        AST::Or(e1, e2) => format!("{}|{}", to_pattern(e1), to_pattern(e2)),
+        AST::Star(e) => format!("{}*", operand(e)),
+        AST::Plus(e) => format!("{}+", operand(e)),
+        AST::Question(e) => format!("{}?", operand(e)),
+        AST::Repeat(e, min, max) => match max {
+            Some(max) if max == min => format!("{}{{{min}}}", operand(e)),
+            Some(max) => format!("{}{{{min},{max}}}", operand(e)),
+            None => format!("{}{{{min},}}", operand(e)),
+        },
+        AST::Capture(_, e) => format!("({})", to_pattern(e)),
+        AST::Backref(n) => format!("\\{n}"),
+        AST::LookAhead(e) => format!("(?={})", to_pattern(e)),
+        AST::NegLookAhead(e) => format!("(?!{})", to_pattern(e)),
+        AST::LookBehind(e) => format!("(?<={})", to_pattern(e)),
+        AST::NegLookBehind(e) => format!("(?<!{})", to_pattern(e)),
+        AST::Atomic(e) => format!("(?>{})", to_pattern(e)),
+    }
+}
+
+/// Prints the operand of a quantifier, grouped unless it is a single atom.
+fn operand(ast: &AST) -> String {
+    match ast {
+        AST::Char(_) | AST::Class(_) | AST::Period | AST::Grapheme | AST::Capture(..) => {
+            to_pattern(ast)
+        }
+        _ => format!("({})", to_pattern(ast)),
+    }
+}
+
+/// Shrinks a failing expression and line while `fails` holds, one smaller
+/// candidate at a time, until no candidate fails.
+pub fn shrink<F>(mut ast: AST, mut line: String, fails: F) -> (AST, String)
+where
+    F: Fn(&AST, &str) -> bool,
+{
+    'outer: loop {
+        for candidate in shrink_ast(&ast) {
+            if fails(&candidate, &line) {
+                ast = candidate;
+                continue 'outer;
+            }
+        }
+        for candidate in shrink_line(&line) {
+            if fails(&ast, &candidate) {
+                line = candidate;
+                continue 'outer;
+            }
+        }
+        return (ast, line);
+    }
+}
+
+/// Returns the lines with one char removed, or replaced with the first char
+/// of the alphabet.
+fn shrink_line(line: &str) -> Vec<String> {
+    let chars = line.chars().collect::<Vec<_>>();
+    let mut candidates = Vec::new();
+    for i in 0..chars.len() {
+        let mut removed = chars.clone();
+        removed.remove(i);
+        candidates.push(removed.into_iter().collect());
+    }
+    for i in 0..chars.len() {
+        if chars[i] != ALPHABET[0] {
+            let mut replaced = chars.clone();
+            replaced[i] = ALPHABET[0];
+            candidates.push(replaced.into_iter().collect());
+        }
+    }
+    candidates
+}
+
+/// Returns the expressions one step smaller than `ast`: a subexpression in
+/// place of its parent, a sequence without one of its elements, a lower
+/// repetition count, or one of these changes applied to a subexpression.
+fn shrink_ast(ast: &AST) -> Vec<AST> {
+    let mut candidates = Vec::new();
+    match ast {
+        AST::Char(c) if *c != ALPHABET[0] => candidates.push(AST::Char(ALPHABET[0])),
+        AST::Class(_) | AST::Period => candidates.push(AST::Char(ALPHABET[0])),
+        AST::Seq(seq) => {
+            for i in 0..seq.len() {
+                let mut rest = seq.clone();
+                let removed = rest.remove(i);
+                candidates.push(removed);
+                candidates.push(match rest.len() {
+                    1 => rest.pop().unwrap(),
+                    _ => AST::Seq(rest),
+                });
+            }
+            for i in 0..seq.len() {
+                for e in shrink_ast(&seq[i]) {
+                    let mut seq = seq.clone();
+                    seq[i] = e;
+                    candidates.push(AST::Seq(seq));
+                }
+            }
+        }
+        AST::Or(e1, e2) => {
+            candidates.push((**e1).clone());
+            candidates.push((**e2).clone());
+            for e in shrink_ast(e1) {
+                candidates.push(AST::Or(Box::new(e), e2.clone()));
+            }
+            for e in shrink_ast(e2) {
+                candidates.push(AST::Or(e1.clone(), Box::new(e)));
+            }
+        }
+        AST::Repeat(e, min, max) => {
+            candidates.push((**e).clone());
+            if *min > 0 {
+                candidates.push(AST::Repeat(e.clone(), min - 1, max.map(|m| m - 1)));
+            }
+            if let Some(m) = max.filter(|m| m > &(min + 1)) {
+                candidates.push(AST::Repeat(e.clone(), *min, Some(min + (m - min) / 2)));
+            }
+            if max.is_some_and(|m| m > *min) {
+                candidates.push(AST::Repeat(e.clone(), *min, max.map(|m| m - 1)));
+            }
+            for e in shrink_ast(e) {
+                candidates.push(AST::Repeat(Box::new(e), *min, *max));
+            }
+        }
+        AST::Star(e)
+        | AST::Plus(e)
+        | AST::Question(e)
+        | AST::Capture(_, e)
+        | AST::LookAhead(e)
+        | AST::NegLookAhead(e)
+        | AST::LookBehind(e)
+        | AST::NegLookBehind(e)
+        | AST::Atomic(e) => {
+            candidates.push((**e).clone());
+            for sub in shrink_ast(e) {
+                candidates.push(rebuild(ast, sub));
+            }
+        }
+        _ => {}
+    }
+    candidates
+}
+
+/// Returns `ast` with its operand replaced.
+fn rebuild(ast: &AST, e: AST) -> AST {
+    let e = Box::new(e);
+    match ast {
+        AST::Star(_) => AST::Star(e),
+        AST::Plus(_) => AST::Plus(e),
+        AST::Question(_) => AST::Question(e),
+        AST::Capture(n, _) => AST::Capture(*n, e),
+        AST::LookAhead(_) => AST::LookAhead(e),
+        AST::NegLookAhead(_) => AST::NegLookAhead(e),
+        AST::LookBehind(_) => AST::LookBehind(e),
+        AST::NegLookBehind(_) => AST::NegLookBehind(e),
+        AST::Atomic(_) => AST::Atomic(e),
+        _ => unreachable!("not a unary operator"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, reference, shrink, to_pattern, Fuzzer};
+    use crate::engine::parser::{self, AST};
+    use rstest::*;
+
+    fn reference_is_match(expr: &str, line: &str) -> bool {
+        let ast = parser::parse(expr).unwrap();
+        let chars = line.chars().collect::<Vec<_>>();
+        (0..=chars.len()).any(|i| reference(&ast, &chars, i, &mut |_| true))
+    }
+
+    #[rstest]
+    #[case("abc", "xabcx", true)]
+    #[case("^a(b|c)*d$", "abcbd", true)]
+    #[case("^a(b|c)*d$", "abcbdx", false)]
+    #[case("a{2,3}b", "aab", true)]
+    #[case("^a{2,3}b", "ab", false)]
+    #[case("(a*)*b", "aaaa", false)]
+    #[case("(?>a*)a", "aaa", false)]
+    #[case("(?>a|ab)c", "abc", false)]
+    #[case("a(?=b)", "ab", true)]
+    #[case("a(?!b)", "ab", false)]
+    #[case("(?<=a)b", "ab", true)]
+    #[case("(?<!a)b", "ab", false)]
+    #[case("[^ab]+", "abc", true)]
+    fn test_reference(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        assert_eq!(reference_is_match(expr, line), expected);
+    }
+
+    #[rstest]
+    #[case("a(b|c)*d")]
+    #[case("(ab)+|c?")]
+    #[case("[^a-c]{2,}[bd]{1,3}x{2}")]
+    #[case("(?>a*)(?=b)(?<!c)\\.")]
+    #[case("^(a|b)$")]
+    fn test_to_pattern(#[case] expr: &str) {
+        let pattern = to_pattern(&parser::parse(expr).unwrap());
+        assert_eq!(to_pattern(&parser::parse(&pattern).unwrap()), pattern);
+    }
+
+    #[test]
+    fn test_shrink() {
+        // a failure that needs some `b` in both the expression and the line
+        let ast = parser::parse("(a|cb)*d?[bc]").unwrap();
+        let fails = |ast: &AST, line: &str| to_pattern(ast).contains('b') && line.contains('b');
+        let (ast, line) = shrink(ast, "dcabb".to_string(), fails);
+        assert_eq!((to_pattern(&ast).as_str(), line.as_str()), ("b", "b"));
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(2)]
+    #[case(3)]
+    #[case(4)]
+    fn test_fuzz(#[case] seed: u64) {
+        if let Err(mismatch) = Fuzzer::new(seed).run(300) {
+            panic!("{mismatch}");
+        }
+    }
+
+    #[rstest]
+    #[case("(a|ab)(c|bcd)(d*)", "abcd")]
+    #[case("(?>(a|)*)b", "ab")]
+    #[case("((?<=a)|b)+c", "abc")]
+    fn test_check(#[case] expr: &str, #[case] line: &str) {
+        assert_eq!(check(&parser::parse(expr).unwrap(), line), Ok(()));
+    }
+}
@@ -0,0 +1,274 @@
+//! Shell-style glob patterns translated into expressions. A pattern matches
+//! a whole path, and only `**` matches across the `/` separator.
+//!
+//! - `*` matches any run of chars within one path component
+//! - `?` matches one char other than `/`
+//! - `**` as a whole component matches any number of components
+//! - `[a-c]`, `[!a-c]` and `[^a-c]` match one char of or not of the set
+//! - `{a,b}` matches one of the comma separated alternatives
+//! - `\c` matches `c` itself
+
+use std::{iter::Peekable, str::CharIndices};
+
+use super::{
+    class::CharClass,
+    parser::{self, ParseError, ParseErrorKind, Syntax, AST},
+    Regex, RegexError,
+};
+
+/// Compiles a glob pattern with options.
+#[derive(Debug, Clone)]
+pub struct GlobBuilder {
+    pattern: String,
+    is_case_insensitive: bool,
+}
+
+impl GlobBuilder {
+    pub fn new(pattern: &str) -> GlobBuilder {
+        GlobBuilder {
+            pattern: pattern.to_string(),
+            is_case_insensitive: false,
+        }
+    }
+
+    /// Matches letters regardless of case. Ranges in brackets are folded for
+    /// ASCII letters only.
+    pub fn case_insensitive(mut self, is_case_insensitive: bool) -> Self {
+        self.is_case_insensitive = is_case_insensitive;
+        self
+    }
+
+    pub fn build(&self) -> Result<Regex, RegexError> {
+        let ast = parse(&self.pattern, self.is_case_insensitive)?;
+        Regex::from_ast(&ast)
+    }
+}
+
+fn parse(pattern: &str, is_case_insensitive: bool) -> Result<AST, ParseError> {
+    let mut glob_parser = GlobParser {
+        pattern,
+        chars: pattern.char_indices().peekable(),
+        is_case_insensitive,
+    };
+    let (seq, _) = glob_parser.parse_seq(false)?;
+
+    let mut anchored = vec![AST::Caret];
+    anchored.extend(seq);
+    anchored.push(AST::Dollar);
+    Ok(AST::Seq(anchored))
+}
+
+struct GlobParser<'a> {
+    pattern: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    is_case_insensitive: bool,
+}
+
+impl<'a> GlobParser<'a> {
+    /// Parses up to the end of the pattern, or up to an unescaped `,` or `}`
+    /// inside braces. Returns the parsed expressions and the terminating
+    /// char.
+    fn parse_seq(&mut self, is_in_brace: bool) -> Result<(Vec<AST>, Option<char>), ParseError> {
+        let mut seq = Vec::new();
+        while let Some((i, c)) = self.chars.next() {
+            match c {
+                ',' | '}' if is_in_brace => return Ok((seq, Some(c))),
+                '*' => seq.push(self.parse_star(i)),
+                '?' => seq.push(AST::Class(not_separator())),
+                '[' => seq.push(self.parse_bracket(i)?),
+                '{' => seq.push(self.parse_brace(i)?),
+                '\\' => match self.chars.next() {
+                    Some((_, c)) => seq.push(self.char(c)),
+                    None => {
+                        return Err(ParseError::new(
+                            ParseErrorKind::TrailingBackslash,
+                            i..self.pattern.len(),
+                            self.pattern,
+                        ))
+                    }
+                },
+                _ => seq.push(self.char(c)),
+            }
+        }
+        Ok((seq, None))
+    }
+
+    /// `*` followed by `*` at byte offset `pos`. `**/` and a trailing `**`
+    /// span components only when they form a whole component.
+    fn parse_star(&mut self, pos: usize) -> AST {
+        if self.chars.next_if(|(_, c)| *c == '*').is_none() {
+            return AST::Star(Box::new(AST::Class(not_separator())));
+        }
+        while self.chars.next_if(|(_, c)| *c == '*').is_some() {}
+
+        let is_component_start = pos == 0 || self.pattern[..pos].ends_with('/');
+        if is_component_start {
+            if self.chars.next_if(|(_, c)| *c == '/').is_some() {
+                // zero or more components, each followed by a separator
+                return AST::Star(Box::new(AST::Seq(vec![
+                    AST::Star(Box::new(AST::Class(not_separator()))),
+                    AST::Char('/'),
+                ])));
+            }
+            if self.chars.peek().is_none() {
+                return AST::Star(Box::new(AST::Period));
+            }
+        }
+        AST::Star(Box::new(AST::Class(not_separator())))
+    }
+
+    fn parse_bracket(&mut self, pos: usize) -> Result<AST, ParseError> {
+        let is_negated = self
+            .chars
+            .next_if(|(_, c)| *c == '!' || *c == '^')
+            .is_some();
+        let class = parser::parse_bracket_items(&mut self.chars, pos, self.pattern, Syntax::Perl)?;
+        let class = if self.is_case_insensitive {
+            fold_case(&class)
+        } else {
+            class
+        };
+
+        // a bracket never matches the separator, so it is added to the chars
+        // that the class must not match before negating them
+        let mut excluded = if is_negated {
+            class.ranges().to_vec()
+        } else {
+            class.negate().ranges().to_vec()
+        };
+        excluded.push(('/', '/'));
+        Ok(AST::Class(CharClass::new(excluded).negate()))
+    }
+
+    fn parse_brace(&mut self, pos: usize) -> Result<AST, ParseError> {
+        let mut alternatives = Vec::new();
+        loop {
+            let (seq, terminator) = self.parse_seq(true)?;
+            alternatives.push(AST::Seq(seq));
+            match terminator {
+                Some(',') => {}
+                Some(_) => break,
+                None => {
+                    return Err(ParseError::new(
+                        ParseErrorKind::NoRightBrace,
+                        pos..pos + 1,
+                        self.pattern,
+                    ))
+                }
+            }
+        }
+        let last = alternatives.pop().unwrap_or(AST::Seq(Vec::new()));
+        Ok(alternatives
+            .into_iter()
+            .rev()
+            .fold(last, |acc, e| AST::Or(Box::new(e), Box::new(acc))))
+    }
+
+    fn char(&self, c: char) -> AST {
+        if !self.is_case_insensitive {
+            return AST::Char(c);
+        }
+        let class = fold_case(&CharClass::new(vec![(c, c)]));
+        match class.ranges() {
+            [(lo, hi)] if lo == hi => AST::Char(c),
+            _ => AST::Class(class),
+        }
+    }
+}
+
+fn not_separator() -> CharClass {
+    CharClass::new(vec![('/', '/')]).negate()
+}
+
+/// Adds the other case of the letters in the class. Single chars are folded
+/// with the full Unicode mappings, ranges only for ASCII letters.
+fn fold_case(class: &CharClass) -> CharClass {
+    let mut ranges = class.ranges().to_vec();
+    for (lo, hi) in class.ranges() {
+        if lo == hi {
+            for c in lo.to_lowercase().chain(lo.to_uppercase()) {
+                ranges.push((c, c));
+            }
+        }
+        for (from, to) in [('a', 'A'), ('A', 'a')] {
+            let (start, end) = ((*lo).max(from), (*hi).min((from as u8 + 25) as char));
+            if start <= end {
+                let shift = |c: char| (c as u8 - from as u8 + to as u8) as char;
+                ranges.push((shift(start), shift(end)));
+            }
+        }
+    }
+    CharClass::new(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GlobBuilder;
+    use crate::engine::{ParseErrorKind, RegexError};
+    use rstest::*;
+
+    #[rstest]
+    #[case("*.rs", "main.rs", true)]
+    #[case("*.rs", "src/main.rs", false)]
+    #[case("*.rs", "main.rsx", false)]
+    #[case("src/**/mod.rs", "src/mod.rs", true)]
+    #[case("src/**/mod.rs", "src/engine/glob/mod.rs", true)]
+    #[case("src/**/mod.rs", "lib/src/mod.rs", false)]
+    #[case("src/**", "src/a/b.rs", true)]
+    #[case("**", "a/b/c", true)]
+    #[case("a**b", "axb", true)]
+    #[case("a**b", "a/b", false)]
+    #[case("[!a-c]?", "dx", true)]
+    #[case("[!a-c]?", "bx", false)]
+    #[case("[!a-c]?", "/x", false)]
+    #[case("?", "/", false)]
+    #[case("[^a]", "b", true)]
+    #[case("[]]", "]", true)]
+    #[case("*.{rs,toml}", "Cargo.toml", true)]
+    #[case("*.{rs,toml}", "Cargo.lock", false)]
+    #[case("{src/{lib,main},build}.rs", "src/main.rs", true)]
+    #[case("{src/{lib,main},build}.rs", "build.rs", true)]
+    #[case("{a,}b", "b", true)]
+    #[case("a,b}", "a,b}", true)]
+    #[case("\\*.rs", "*.rs", true)]
+    #[case("\\*.rs", "a.rs", false)]
+    #[case("(a|b)+.rs", "(a|b)+.rs", true)]
+    fn test_glob(#[case] pattern: &str, #[case] path: &str, #[case] expected: bool) {
+        let regex = GlobBuilder::new(pattern).build().unwrap();
+        assert_eq!(regex.is_match(path, true).unwrap(), expected);
+        assert_eq!(regex.is_match(path, false).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("*.RS", "main.rs", true)]
+    #[case("README.*", "readme.md", true)]
+    #[case("[a-c]x", "Bx", true)]
+    #[case("[!a-c]x", "Bx", false)]
+    #[case("ÄB", "äb", true)]
+    #[case("*.rs", "main.r", false)]
+    fn test_case_insensitive(#[case] pattern: &str, #[case] path: &str, #[case] expected: bool) {
+        let regex = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert_eq!(regex.is_match(path, true).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("*.{rs,toml", ParseErrorKind::NoRightBrace, 2..3)]
+    #[case("[a-c", ParseErrorKind::NoRightBracket, 0..1)]
+    #[case("a\\", ParseErrorKind::TrailingBackslash, 1..2)]
+    fn test_glob_error(
+        #[case] pattern: &str,
+        #[case] kind: ParseErrorKind,
+        #[case] span: std::ops::Range<usize>,
+    ) {
+        match GlobBuilder::new(pattern).build() {
+            Err(RegexError::Parse(e)) => {
+                assert_eq!(e.kind, kind);
+                assert_eq!(e.span, span);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,526 @@
+//! Native code for the search automaton of an expression. The DFA over chars
+//! is lowered to a DFA over the UTF-8 bytes of the line, and each state of it
+//! becomes a block of x86-64 code reading one byte and jumping to the block
+//! of the next state.
+//!
+//! The code is placed in an executable mapping on x86-64 Linux only. On other
+//! targets, and for programs without a DFA such as those with lookaround or
+//! backreferences, matching falls back to the depth-first evaluator. So do
+//! most unanchored repetitions compiled to counter loops, whose search
+//! automaton has a state for each set of counts that may be running at once.
+
+use std::collections::HashMap;
+
+use super::{
+    dfa::{Alphabet, Dfa, Mode},
+    Regex, RegexError,
+};
+
+/// An expression with native code for `is_match` if it could be compiled.
+#[derive(Debug)]
+pub struct JitRegex {
+    regex: Regex,
+    native: Option<native::Code>,
+}
+
+impl JitRegex {
+    pub fn new(regex: &Regex) -> JitRegex {
+        let native = compile(regex).and_then(|code| native::Code::new(&code));
+        JitRegex {
+            regex: regex.clone(),
+            native,
+        }
+    }
+
+    /// Returns `true` if matching runs native code rather than the evaluator.
+    pub fn is_native(&self) -> bool {
+        self.native.is_some()
+    }
+
+    /// Same as `Regex::is_match` in depth-first mode.
+    pub fn is_match(&self, line: &str) -> Result<bool, RegexError> {
+        match &self.native {
+            Some(code) => Ok(code.call(line)),
+            None => self.regex.is_match(line, true),
+        }
+    }
+}
+
+/// Returns the machine code of the program, or `None` if it has no DFA.
+fn compile(regex: &Regex) -> Option<Vec<u8>> {
+    let alphabet = Alphabet::new(&[&regex.code]);
+    let dfa = Dfa::new(&regex.code, &alphabet, Mode::Search).ok()?;
+    let rows = lower(&dfa, &alphabet);
+    Some(assemble(&dfa, &rows))
+}
+
+/// ranges of the UTF-8 bytes of a code point range, one per byte
+type Seq = Vec<(u8, u8)>;
+
+/// Row of the byte automaton. `row[b]` is the row reached by reading `b`,
+/// or `None` if no valid UTF-8 continues with `b`.
+type Row = [Option<usize>; 256];
+
+/// Builds the byte automaton. Row `i` is state `i` of the DFA for every state
+/// of it, and the rows after them are the states in the middle of a char.
+fn lower(dfa: &Dfa, alphabet: &Alphabet) -> Vec<Row> {
+    let mut rows = vec![[None; 256]; dfa.trans.len()];
+    // the rows in the middle of a char, by the rest of the sequences and
+    // their targets, shared by all states
+    let mut memo = HashMap::new();
+
+    for state in 0..dfa.trans.len() {
+        let mut ranges: Vec<(u32, u32, usize)> = Vec::new();
+        for (class, next) in dfa.trans[state].iter().enumerate() {
+            let (lo, hi) = alphabet.range(class);
+            match ranges.last_mut() {
+                Some((_, last_hi, last_next)) if *last_next == *next && *last_hi + 1 == lo => {
+                    *last_hi = hi
+                }
+                _ => ranges.push((lo, hi, *next)),
+            }
+        }
+        let seqs = ranges
+            .into_iter()
+            .flat_map(|(lo, hi, next)| utf8_sequences(lo, hi).into_iter().map(move |s| (s, next)))
+            .collect::<Vec<_>>();
+        rows[state] = build_row(&seqs, &mut rows, &mut memo);
+    }
+    rows
+}
+
+fn build_row(
+    seqs: &[(Seq, usize)],
+    rows: &mut Vec<Row>,
+    memo: &mut HashMap<Vec<(Seq, usize)>, usize>,
+) -> Row {
+    let mut row = [None; 256];
+    for (b, edge) in row.iter_mut().enumerate() {
+        let b = b as u8;
+        let matching = seqs
+            .iter()
+            .filter(|(seq, _)| seq[0].0 <= b && b <= seq[0].1)
+            .collect::<Vec<_>>();
+        *edge = match matching.as_slice() {
+            [] => None,
+            [(seq, next), ..] if seq.len() == 1 => Some(*next),
+            _ => {
+                let rest = matching
+                    .iter()
+                    .map(|(seq, next)| (seq[1..].to_vec(), *next))
+                    .collect::<Vec<_>>();
+                match memo.get(&rest) {
+                    Some(id) => Some(*id),
+                    None => {
+                        let child = build_row(&rest, rows, memo);
+                        rows.push(child);
+                        memo.insert(rest, rows.len() - 1);
+                        Some(rows.len() - 1)
+                    }
+                }
+            }
+        };
+    }
+    row
+}
+
+/// Splits a range of code points into ranges of UTF-8 sequences, so that the
+/// encodings of the range are exactly the products of the byte ranges of
+/// each sequence. Surrogates are left out.
+fn utf8_sequences(lo: u32, hi: u32) -> Vec<Seq> {
+    let mut seqs = Vec::new();
+    let mut stack = vec![(lo, hi)];
+    'outer: while let Some((lo, hi)) = stack.pop() {
+        if lo <= 0xDFFF && 0xD800 <= hi {
+            if 0xDFFF < hi {
+                stack.push((0xE000, hi));
+            }
+            if lo < 0xD800 {
+                stack.push((lo, 0xD7FF));
+            }
+            continue;
+        }
+        // the largest code point encoded in one, two and three bytes
+        if let Some(max) = [0x7F, 0x7FF, 0xFFFF]
+            .into_iter()
+            .find(|m| lo <= *m && *m < hi)
+        {
+            stack.push((max + 1, hi));
+            stack.push((lo, max));
+            continue;
+        }
+        // the trailing bytes of lo and hi must span all of their range unless
+        // the bytes before them are the same
+        for i in 1..4 {
+            let mask = (1 << (6 * i)) - 1;
+            if lo & !mask != hi & !mask {
+                if lo & mask != 0 {
+                    stack.push(((lo | mask) + 1, hi));
+                    stack.push((lo, lo | mask));
+                    continue 'outer;
+                }
+                if hi & mask != mask {
+                    stack.push((hi & !mask, hi));
+                    stack.push((lo, (hi & !mask) - 1));
+                    continue 'outer;
+                }
+            }
+        }
+
+        let (mut lo_buf, mut hi_buf) = ([0; 4], [0; 4]);
+        let lo_bytes = char::from_u32(lo)
+            .unwrap()
+            .encode_utf8(&mut lo_buf)
+            .as_bytes();
+        let hi_bytes = char::from_u32(hi)
+            .unwrap()
+            .encode_utf8(&mut hi_buf)
+            .as_bytes();
+        seqs.push(
+            lo_bytes
+                .iter()
+                .copied()
+                .zip(hi_bytes.iter().copied())
+                .collect(),
+        );
+    }
+    seqs
+}
+
+/// Returns which states accept whatever follows them, and which states
+/// cannot reach an accepting state at all.
+fn final_and_dead_states(dfa: &Dfa) -> (Vec<bool>, Vec<bool>) {
+    let mut is_final = dfa.accepting.clone();
+    let mut is_changed = true;
+    while is_changed {
+        is_changed = false;
+        for state in 0..dfa.trans.len() {
+            if is_final[state] && dfa.trans[state].iter().any(|next| !is_final[*next]) {
+                is_final[state] = false;
+                is_changed = true;
+            }
+        }
+    }
+    let is_dead = dfa.live_states().into_iter().map(|live| !live).collect();
+    (is_final, is_dead)
+}
+
+/// Machine code with jumps to labels resolved once all labels are placed.
+struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// position of each rel32 operand and the label it refers to
+    fixups: Vec<(usize, usize)>,
+}
+
+impl Assembler {
+    fn bind(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// Emits an instruction ending with a rel32 operand pointing at `label`.
+    fn emit_jump(&mut self, opcode: &[u8], label: usize) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), label));
+        self.emit(&[0; 4]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for (pos, label) in self.fixups {
+            let target = self.labels[label].expect("jump to an unbound label");
+            let rel = target as i64 - (pos + 4) as i64;
+            self.code[pos..pos + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+        }
+        self.code
+    }
+}
+
+// x86-64 encodings used by the generated code
+const ADD_RSI_RDI: &[u8] = &[0x48, 0x01, 0xFE];
+const CMP_RDI_RSI: &[u8] = &[0x48, 0x39, 0xF7];
+const MOVZX_EAX_BYTE_RDI: &[u8] = &[0x0F, 0xB6, 0x07];
+const INC_RDI: &[u8] = &[0x48, 0xFF, 0xC7];
+const CMP_AL: u8 = 0x3C;
+const JE: &[u8] = &[0x0F, 0x84];
+const JBE: &[u8] = &[0x0F, 0x86];
+const JMP: &[u8] = &[0xE9];
+const MOV_EAX_1_RET: &[u8] = &[0xB8, 0x01, 0x00, 0x00, 0x00, 0xC3];
+const XOR_EAX_EAX_RET: &[u8] = &[0x31, 0xC0, 0xC3];
+
+/// Generates `extern "C" fn(ptr: *const u8, len: usize) -> u32`, returning
+/// 1 if the bytes contain a match. `rdi` points at the next byte and `rsi`
+/// at the end of the line.
+///
+/// ```text
+/// L<row>:  cmp rdi, rsi
+///          je  <accept or reject>
+///          movzx eax, byte [rdi]
+///          inc rdi
+///          cmp al, <last byte of range>
+///          jbe L<next row>
+///          ...
+///          jmp L<next row>
+/// ```
+fn assemble(dfa: &Dfa, rows: &[Row]) -> Vec<u8> {
+    let (is_final, is_dead) = final_and_dead_states(dfa);
+    let (accept, reject) = (rows.len(), rows.len() + 1);
+    let label = |row: Option<usize>| match row {
+        Some(row) if row < dfa.trans.len() && is_final[row] => accept,
+        Some(row) if row < dfa.trans.len() && is_dead[row] => reject,
+        Some(row) => row,
+        None => reject,
+    };
+
+    let mut asm = Assembler {
+        code: Vec::new(),
+        labels: vec![None; rows.len() + 2],
+        fixups: Vec::new(),
+    };
+    asm.emit(ADD_RSI_RDI);
+    if label(Some(0)) != 0 {
+        asm.emit_jump(JMP, label(Some(0)));
+    }
+
+    for (i, row) in rows.iter().enumerate() {
+        if label(Some(i)) != i {
+            continue;
+        }
+        asm.bind(i);
+        asm.emit(CMP_RDI_RSI);
+        let is_accepting = i < dfa.trans.len() && dfa.accepting[i];
+        asm.emit_jump(JE, if is_accepting { accept } else { reject });
+        asm.emit(MOVZX_EAX_BYTE_RDI);
+        asm.emit(INC_RDI);
+
+        let mut b = 0;
+        while b < 256 {
+            let target = label(row[b]);
+            let mut last = b;
+            while last < 255 && label(row[last + 1]) == target {
+                last += 1;
+            }
+            if last == 255 {
+                asm.emit_jump(JMP, target);
+            } else {
+                asm.emit(&[CMP_AL, last as u8]);
+                asm.emit_jump(JBE, target);
+            }
+            b = last + 1;
+        }
+    }
+
+    asm.bind(accept);
+    asm.emit(MOV_EAX_1_RET);
+    asm.bind(reject);
+    asm.emit(XOR_EAX_EAX_RET);
+    asm.finish()
+}
+
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod native {
+    use std::{ffi::c_void, ptr};
+
+    const PROT_READ: i32 = 1;
+    const PROT_WRITE: i32 = 2;
+    const PROT_EXEC: i32 = 4;
+    const MAP_PRIVATE: i32 = 2;
+    const MAP_ANONYMOUS: i32 = 0x20;
+    const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: i32,
+            flags: i32,
+            fd: i32,
+            offset: i64,
+        ) -> *mut c_void;
+        fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+        fn munmap(addr: *mut c_void, len: usize) -> i32;
+    }
+
+    /// Machine code in a mapping that is writable while it is copied in and
+    /// executable afterwards, never both.
+    #[derive(Debug)]
+    pub struct Code {
+        addr: *mut c_void,
+        len: usize,
+    }
+
+    // the mapping is never written after it is made executable
+    unsafe impl Send for Code {}
+    unsafe impl Sync for Code {}
+
+    impl Code {
+        pub fn new(code: &[u8]) -> Option<Code> {
+            let len = code.len();
+            unsafe {
+                let addr = mmap(
+                    ptr::null_mut(),
+                    len,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS,
+                    -1,
+                    0,
+                );
+                if addr == MAP_FAILED {
+                    return None;
+                }
+                ptr::copy_nonoverlapping(code.as_ptr(), addr as *mut u8, len);
+                if mprotect(addr, len, PROT_READ | PROT_EXEC) != 0 {
+                    munmap(addr, len);
+                    return None;
+                }
+                Some(Code { addr, len })
+            }
+        }
+
+        pub fn call(&self, line: &str) -> bool {
+            // the code only reads the bytes from `ptr` to `ptr + len`
+            unsafe {
+                let f: extern "C" fn(*const u8, usize) -> u32 = std::mem::transmute(self.addr);
+                f(line.as_ptr(), line.len()) != 0
+            }
+        }
+    }
+
+    impl Drop for Code {
+        fn drop(&mut self) {
+            unsafe {
+                munmap(self.addr, self.len);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+mod native {
+    /// Native code is not supported on this target.
+    #[derive(Debug)]
+    pub enum Code {}
+
+    impl Code {
+        pub fn new(_code: &[u8]) -> Option<Code> {
+            None
+        }
+
+        pub fn call(&self, _line: &str) -> bool {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{utf8_sequences, JitRegex};
+    use crate::engine::{sample::Sampler, Regex};
+    use rstest::*;
+
+    const IS_SUPPORTED: bool = cfg!(all(target_arch = "x86_64", target_os = "linux"));
+
+    #[rstest]
+    #[case(0x00, 0x7F)]
+    #[case(0x61, 0x3042)]
+    #[case(0x7FF, 0x800)]
+    #[case(0xD000, 0xE0FF)]
+    #[case(0xFFF0, 0x10FFFF)]
+    fn test_utf8_sequences(#[case] lo: u32, #[case] hi: u32) {
+        let seqs = utf8_sequences(lo, hi);
+        let is_covered = |c: char| {
+            let mut buf = [0; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes();
+            seqs.iter().any(|seq| {
+                seq.len() == bytes.len()
+                    && seq.iter().zip(bytes).all(|((l, h), b)| l <= b && b <= h)
+            })
+        };
+        for cp in lo.saturating_sub(0x100)..=(hi + 0x100).min(char::MAX as u32) {
+            if let Some(c) = char::from_u32(cp) {
+                assert_eq!(is_covered(c), (lo..=hi).contains(&cp), "{cp:#x}");
+            }
+        }
+    }
+
+    #[rstest]
+    #[case("abc", &["abc", "xxabcxx", "ab", "", "abxabc"])]
+    #[case("^abc$", &["abc", "abcc", "xabc", ""])]
+    #[case("a(b|c)*d$", &["abcbd", "abcbdx", "ad", "xad", "a"])]
+    #[case("(a+)+b", &["aaab", "aaaa", "b", ""])]
+    #[case("a?a?a?aaa", &["aa", "aaa", "aaaaa"])]
+    #[case("[[:digit:]]{2,3}x", &["12x", "1x", "a123x", "12"])]
+    #[case("a.c", &["abc", "a\u{3042}c", "a\u{1F600}c", "ac", "a\u{3042}\u{3042}c"])]
+    #[case("\u{3042}+\u{1F600}", &["\u{3042}\u{3042}\u{1F600}", "\u{1F600}", "\u{3043}\u{1F600}"])]
+    #[case("[^a-z\u{e9}]$", &["abc", "abC", "\u{e9}", "\u{e8}", "\u{10FFFF}"])]
+    #[case("\\p{Greek}+$", &["abc\u{3c9}", "\u{3c9}x", "\u{1F00}"])]
+    #[case("$", &["", "a"])]
+    #[case("x*", &["", "abc"])]
+    #[case("^a{20}$", &["aaaaaaaaaaaaaaaaaaaa", "aaaaaaaaaaaaaaaaaaa", "aaaaaaaaaaaaaaaaaaaaa"])]
+    fn test_differential(#[case] expr: &str, #[case] lines: &[&str]) {
+        let regex = Regex::new(expr).unwrap();
+        let jit = JitRegex::new(&regex);
+        assert_eq!(jit.is_native(), IS_SUPPORTED);
+        for line in lines {
+            assert_eq!(
+                jit.is_match(line).unwrap(),
+                regex.is_match(line, true).unwrap(),
+                "{expr} on {line:?}"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("(a|bc)*d+[x-z]?$")]
+    #[case("^(ab|a)(bc|c)?[^b]")]
+    #[case("[[:alpha:]][[:alnum:]_]*=[[:digit:]]")]
+    #[case("\u{3042}.?\u{3044}|\u{1F600}+")]
+    fn test_differential_samples(#[case] expr: &str) {
+        let regex = Regex::new(expr).unwrap();
+        let jit = JitRegex::new(&regex);
+        for seed in 1..50 {
+            let mut sampler = Sampler::new(&regex, seed).near_miss(seed % 2 == 0);
+            if let Some(line) = sampler.sample().unwrap() {
+                let prefix = line
+                    .chars()
+                    .take(line.chars().count() / 2)
+                    .collect::<String>();
+                for line in [line, prefix] {
+                    assert_eq!(
+                        jit.is_match(&line).unwrap(),
+                        regex.is_match(&line, true).unwrap(),
+                        "{expr} on {line:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_counter() {
+        let regex = Regex::new("^x(ab){1001}y?$").unwrap();
+        let jit = JitRegex::new(&regex);
+        assert_eq!(jit.is_native(), IS_SUPPORTED);
+        let line = format!("x{}", "ab".repeat(1001));
+        for line in [line.clone(), format!("{line}y"), format!("{line}ab"), line[..5].to_string()] {
+            assert_eq!(
+                jit.is_match(&line).unwrap(),
+                regex.is_match(&line, true).unwrap(),
+                "{line:?}"
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("(a)\\1", "aa", true)]
+    #[case("a(?=b)", "ac", false)]
+    #[case("a{1001}", "a", false)]
+    fn test_fallback(#[case] expr: &str, #[case] line: &str, #[case] expected: bool) {
+        let jit = JitRegex::new(&Regex::new(expr).unwrap());
+        assert!(!jit.is_native());
+        assert_eq!(jit.is_match(line).unwrap(), expected);
+    }
+}
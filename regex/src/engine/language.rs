@@ -0,0 +1,151 @@
+//! Operations on the sets of inputs accepted by compiled expressions. An input
+//! is accepted when `Regex::is_match` returns `true` for it.
+//!
+//! The operations build the search automaton of each expression. A
+//! repetition compiled to a counter loop is supported, but when it is not
+//! anchored the automaton tracks every count that may be running at once,
+//! and the operations usually fail with `DfaError::TooManyStates`.
+
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use super::{
+    dfa::{Alphabet, Dfa, Mode},
+    Regex, RegexError,
+};
+
+/// Returns `true` if `a` and `b` accept exactly the same inputs.
+pub fn is_equivalent(a: &Regex, b: &Regex) -> Result<bool, RegexError> {
+    Ok(counterexample(a, b)?.is_none())
+}
+
+/// Returns `true` if every input accepted by `a` is accepted by `b`.
+pub fn is_subset(a: &Regex, b: &Regex) -> Result<bool, RegexError> {
+    Ok(find_shortest(a, b, |acc_a, acc_b| acc_a && !acc_b)?.is_none())
+}
+
+/// Returns `true` if no input is accepted by both `a` and `b`.
+pub fn is_intersection_empty(a: &Regex, b: &Regex) -> Result<bool, RegexError> {
+    Ok(intersection_example(a, b)?.is_none())
+}
+
+/// Returns the shortest input accepted by both `a` and `b`.
+pub fn intersection_example(a: &Regex, b: &Regex) -> Result<Option<String>, RegexError> {
+    find_shortest(a, b, |acc_a, acc_b| acc_a && acc_b)
+}
+
+/// Returns the shortest input accepted by only one of `a` and `b`.
+pub fn counterexample(a: &Regex, b: &Regex) -> Result<Option<String>, RegexError> {
+    find_shortest(a, b, |acc_a, acc_b| acc_a != acc_b)
+}
+
+/// states of the two automata
+type Pair = (usize, usize);
+
+/// Searches the product of the two automata breadth first for a pair of
+/// states satisfying `is_target`, and spells out the path to it.
+fn find_shortest<F>(a: &Regex, b: &Regex, is_target: F) -> Result<Option<String>, RegexError>
+where
+    F: Fn(bool, bool) -> bool,
+{
+    let alphabet = Alphabet::new(&[&a.code, &b.code]);
+    let dfa_a = Dfa::new(&a.code, &alphabet, Mode::Search)?;
+    let dfa_b = Dfa::new(&b.code, &alphabet, Mode::Search)?;
+
+    // reached pair -> previous pair and the class read from it
+    let mut prev: HashMap<Pair, Option<(Pair, usize)>> = HashMap::from([((0, 0), None)]);
+    let mut queue = VecDeque::from([(0, 0)]);
+
+    while let Some((sa, sb)) = queue.pop_front() {
+        if is_target(dfa_a.accepting[sa], dfa_b.accepting[sb]) {
+            let mut classes = Vec::new();
+            let mut pair = (sa, sb);
+            while let Some(Some((p, class))) = prev.get(&pair) {
+                classes.push(*class);
+                pair = *p;
+            }
+            let s = classes
+                .into_iter()
+                .rev()
+                .map(|class| alphabet.representative(class))
+                .collect();
+            return Ok(Some(s));
+        }
+
+        for class in 0..alphabet.len() {
+            let next = (dfa_a.trans[sa][class], dfa_b.trans[sb][class]);
+            if let Entry::Vacant(e) = prev.entry(next) {
+                e.insert(Some(((sa, sb), class)));
+                queue.push_back(next);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{counterexample, is_equivalent, is_intersection_empty, is_subset};
+    use crate::engine::{DfaError, Regex, RegexError};
+    use rstest::*;
+
+    #[rstest]
+    #[case("(a|b)*", "(a*b*)*", true)]
+    #[case("a+", "aa*", true)]
+    #[case("^ab|^ac", "^a(b|c)", true)]
+    #[case("abc", "b", false)]
+    #[case("a", "a.*", true)]
+    #[case("^a$", "^a", false)]
+    #[case("^a{20}$", "^a{10}a{10}$", true)]
+    #[case("^a{20}$", "^a{19}$", false)]
+    #[case("^a{1001}$", "^a{1000}a$", true)]
+    #[case("^(ab){1001}$", "^(ab){1002}$", false)]
+    fn test_equivalent(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let (a, b) = (Regex::new(a).unwrap(), Regex::new(b).unwrap());
+        assert_eq!(is_equivalent(&a, &b).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^abc$", "b", true)]
+    #[case("b", "^abc$", false)]
+    #[case("^a+$", "^a*$", true)]
+    fn test_subset(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let (a, b) = (Regex::new(a).unwrap(), Regex::new(b).unwrap());
+        assert_eq!(is_subset(&a, &b).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^a+$", "^b+$", true)]
+    #[case("^a+$", "b", true)]
+    #[case("^a", "b$", false)]
+    fn test_intersection(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        let (a, b) = (Regex::new(a).unwrap(), Regex::new(b).unwrap());
+        assert_eq!(is_intersection_empty(&a, &b).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("^a$", "^a", Some("aA"))]
+    #[case("^(ab)*$", "^(ab|ba)*$", Some("ba"))]
+    #[case("^.$", "^a$", Some("A"))]
+    #[case("x", "x", None)]
+    fn test_counterexample(#[case] a: &str, #[case] b: &str, #[case] expected: Option<&str>) {
+        let (a, b) = (Regex::new(a).unwrap(), Regex::new(b).unwrap());
+        let witness = counterexample(&a, &b).unwrap();
+        assert_eq!(witness.as_deref(), expected);
+        if let Some(s) = witness {
+            assert_ne!(a.is_match(&s, true).unwrap(), b.is_match(&s, true).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let (a, b) = (Regex::new("(a)\\1").unwrap(), Regex::new("aa").unwrap());
+        assert!(is_equivalent(&a, &b).is_err());
+
+        let a = Regex::new("a{1001}").unwrap();
+        assert!(matches!(
+            is_equivalent(&a, &a),
+            Err(RegexError::Dfa(DfaError::TooManyStates))
+        ));
+    }
+}
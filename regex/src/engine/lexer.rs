@@ -0,0 +1,224 @@
+use std::{error::Error, fmt::Display, ops::Range};
+
+use super::{
+    codegen::{self, Instruction},
+    evaluator::{self, EvalError},
+    parser, RegexError,
+};
+
+#[derive(Debug)]
+pub enum LexError {
+    /// no rule matches the input starting at this byte offset
+    NoMatch(usize),
+    Eval(EvalError),
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexError::NoMatch(pos) => write!(f, "no rule matches input: pos = {pos}"),
+            LexError::Eval(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for LexError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token<'a, K> {
+    pub kind: K,
+    /// byte range in the input
+    pub span: Range<usize>,
+    pub text: &'a str,
+}
+
+/// Collects `(pattern, token kind)` rules. Rules added earlier win when two
+/// rules match the same longest input.
+pub struct LexerBuilder<K> {
+    rules: Vec<(String, K)>,
+}
+
+impl<K> Default for LexerBuilder<K> {
+    fn default() -> Self {
+        LexerBuilder { rules: Vec::new() }
+    }
+}
+
+impl<K: Clone> LexerBuilder<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rule(mut self, pattern: &str, kind: K) -> Self {
+        self.rules.push((pattern.to_string(), kind));
+        self
+    }
+
+    pub fn build(self) -> Result<Lexer<K>, RegexError> {
+        let mut asts = Vec::with_capacity(self.rules.len());
+        let mut kinds = Vec::with_capacity(self.rules.len());
+        for (pattern, kind) in self.rules {
+            asts.push(parser::parse(&pattern)?);
+            kinds.push(kind);
+        }
+        let (code, match_pcs) = codegen::get_rules_code(&asts)?;
+        // the rules are run by the breadth-first evaluator only
+        evaluator::check_breadth_first(&code)?;
+        Ok(Lexer {
+            code,
+            kinds: match_pcs.into_iter().zip(kinds).collect(),
+        })
+    }
+}
+
+/// All rules compiled into a single program.
+pub struct Lexer<K> {
+    code: Vec<Instruction>,
+    /// address of each rule's `match` instruction and its token kind
+    kinds: Vec<(usize, K)>,
+}
+
+impl<K: Clone> Lexer<K> {
+    pub fn tokenize<'a>(&'a self, input: &'a str) -> Tokens<'a, K> {
+        let mut offsets = input.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+        offsets.push(input.len());
+        Tokens {
+            lexer: self,
+            input,
+            line: input.chars().collect(),
+            offsets,
+            sp: 0,
+            is_done: false,
+        }
+    }
+
+    fn kind_of(&self, match_pc: usize) -> Option<&K> {
+        self.kinds
+            .iter()
+            .find(|(pc, _)| *pc == match_pc)
+            .map(|(_, kind)| kind)
+    }
+}
+
+/// Iterator over the tokens of an input. Stops after the first error.
+pub struct Tokens<'a, K> {
+    lexer: &'a Lexer<K>,
+    input: &'a str,
+    line: Vec<char>,
+    /// byte offset of each char, followed by the input length
+    offsets: Vec<usize>,
+    sp: usize,
+    is_done: bool,
+}
+
+impl<'a, K: Clone> Iterator for Tokens<'a, K> {
+    type Item = Result<Token<'a, K>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done || self.sp == self.line.len() {
+            return None;
+        }
+
+        let start = self.offsets[self.sp];
+        if self.lexer.kinds.is_empty() {
+            self.is_done = true;
+            return Some(Err(LexError::NoMatch(start)));
+        }
+        let result = match evaluator::eval_longest(&self.lexer.code, &self.line, self.sp) {
+            Ok(Some((pc, sp))) if sp > self.sp => match self.lexer.kind_of(pc) {
+                Some(kind) => {
+                    let end = self.offsets[sp];
+                    self.sp = sp;
+                    Ok(Token {
+                        kind: kind.clone(),
+                        span: start..end,
+                        text: &self.input[start..end],
+                    })
+                }
+                None => Err(LexError::Eval(EvalError::InvalidPC)),
+            },
+            // an empty match would never advance, so it counts as no match
+            Ok(_) => Err(LexError::NoMatch(start)),
+            Err(e) => Err(LexError::Eval(e)),
+        };
+        self.is_done = result.is_err();
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LexError, LexerBuilder};
+    use crate::engine::{evaluator::EvalError, RegexError};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Kind {
+        If,
+        Ident,
+        Num,
+        Space,
+        Eq,
+        EqEq,
+    }
+
+    fn lexer() -> super::Lexer<Kind> {
+        LexerBuilder::new()
+            .rule("if", Kind::If)
+            .rule("(a|b|c|f|i|x|y)+", Kind::Ident)
+            .rule("(0|1|2|3|4|5|6|7|8|9)+", Kind::Num)
+            .rule(" +", Kind::Space)
+            .rule("=", Kind::Eq)
+            .rule("==", Kind::EqEq)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_longest_and_priority() {
+        let lexer = lexer();
+        let tokens = lexer
+            .tokenize("if iff == 42")
+            .map(|t| t.map(|t| (t.kind, t.span, t.text)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                (Kind::If, 0..2, "if"),
+                (Kind::Space, 2..3, " "),
+                (Kind::Ident, 3..6, "iff"),
+                (Kind::Space, 6..7, " "),
+                (Kind::EqEq, 7..9, "=="),
+                (Kind::Space, 9..10, " "),
+                (Kind::Num, 10..12, "42"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_match() {
+        let lexer = lexer();
+        let mut tokens = lexer.tokenize("x=あ1");
+        assert_eq!(tokens.next().unwrap().unwrap().kind, Kind::Ident);
+        assert_eq!(tokens.next().unwrap().unwrap().span, 1..2);
+        assert!(matches!(tokens.next(), Some(Err(LexError::NoMatch(2)))));
+        assert!(tokens.next().is_none());
+    }
+
+    #[test]
+    fn test_invalid_rule() {
+        assert!(LexerBuilder::new().rule("+a", ()).build().is_err());
+        assert!(LexerBuilder::new().rule("", ()).build().is_err());
+    }
+
+    #[test]
+    fn test_depth_first_only_rule() {
+        for pattern in ["(a)\\1", "(?>a|ab)"] {
+            let result = LexerBuilder::new().rule("b", ()).rule(pattern, ()).build();
+            assert!(matches!(
+                result,
+                Err(RegexError::Eval(EvalError::DepthFirstOnly(_)))
+            ));
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Line-oriented search over large inputs on several threads. The input is
+//! split into chunks ending on line boundaries, and the workers take the
+//! chunks one at a time and match their lines with the same compiled
+//! expression, each with its own `Cache`.
+
+use std::{
+    ops::Range,
+    panic,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use super::{Cache, Regex, RegexError};
+
+/// default number of bytes per chunk, extended to the end of its last line
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A line containing a match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch {
+    /// line number counted from 1
+    pub line_number: usize,
+    /// byte range of the line in the input, without the line terminator
+    pub range: Range<usize>,
+}
+
+/// lines matched in one chunk, numbered from the start of the chunk
+struct ChunkMatches {
+    matches: Vec<LineMatch>,
+    line_count: usize,
+}
+
+/// Searches the lines of an input with a pool of threads.
+#[derive(Debug, Clone)]
+pub struct ParallelSearch<'a> {
+    regex: &'a Regex,
+    threads: usize,
+    chunk_size: usize,
+    is_depth: bool,
+}
+
+impl<'a> ParallelSearch<'a> {
+    pub fn new(regex: &'a Regex) -> ParallelSearch<'a> {
+        ParallelSearch {
+            regex,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: CHUNK_SIZE,
+            is_depth: true,
+        }
+    }
+
+    /// Sets the number of worker threads. Defaults to the available
+    /// parallelism.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the approximate number of bytes given to a worker at a time.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Selects the evaluator as `is_depth` does for `Regex::is_match`.
+    pub fn depth_first(mut self, is_depth: bool) -> Self {
+        self.is_depth = is_depth;
+        self
+    }
+
+    /// Returns the lines of `input` that contain a match, in input order.
+    /// Lines are split as `str::lines` does. If matching fails on some lines,
+    /// the error of the first of them is returned.
+    pub fn search(&self, input: &str) -> Result<Vec<LineMatch>, RegexError> {
+        let chunks = split_chunks(input, self.chunk_size);
+        let next = AtomicUsize::new(0);
+        let workers = self.threads.min(chunks.len());
+
+        let mut results = Vec::with_capacity(chunks.len());
+        results.resize_with(chunks.len(), || None);
+        thread::scope(|scope| {
+            let handles = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut cache = Cache::new();
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            match chunks.get(i) {
+                                Some(chunk) => {
+                                    done.push((i, self.search_chunk(input, chunk, &mut cache)))
+                                }
+                                None => return done,
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                match handle.join() {
+                    Ok(done) => {
+                        for (i, res) in done {
+                            results[i] = Some(res);
+                        }
+                    }
+                    Err(e) => panic::resume_unwind(e),
+                }
+            }
+        });
+
+        let mut matches = Vec::new();
+        let mut lines_before = 0;
+        for res in results {
+            let chunk = res.expect("every chunk is searched")?;
+            matches.extend(chunk.matches.into_iter().map(|m| LineMatch {
+                line_number: lines_before + m.line_number,
+                range: m.range,
+            }));
+            lines_before += chunk.line_count;
+        }
+        Ok(matches)
+    }
+
+    fn search_chunk(
+        &self,
+        input: &str,
+        chunk: &Range<usize>,
+        cache: &mut Cache,
+    ) -> Result<ChunkMatches, RegexError> {
+        let mut matches = Vec::new();
+        let mut start = chunk.start;
+        let mut line_count = 0;
+        for line in input[chunk.clone()].split_inclusive('\n') {
+            line_count += 1;
+            let end = start + line.len();
+            let content = line.strip_suffix('\n').unwrap_or(line);
+            let content = content.strip_suffix('\r').unwrap_or(content);
+            if self.regex.is_match_with(content, self.is_depth, cache)? {
+                matches.push(LineMatch {
+                    line_number: line_count,
+                    range: start..start + content.len(),
+                });
+            }
+            start = end;
+        }
+        Ok(ChunkMatches {
+            matches,
+            line_count,
+        })
+    }
+}
+
+/// Splits the input into ranges of at least `chunk_size` bytes, each ending
+/// just after a newline except the last one.
+fn split_chunks(input: &str, chunk_size: usize) -> Vec<Range<usize>> {
+    let bytes = input.as_bytes();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let end = (start + chunk_size).min(bytes.len());
+        let end = match bytes[end - 1..].iter().position(|b| *b == b'\n') {
+            Some(i) => end + i,
+            None => bytes.len(),
+        };
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_chunks, LineMatch, ParallelSearch};
+    use crate::engine::{EvalError, Regex, RegexError};
+    use rstest::*;
+
+    #[rstest]
+    #[case("", 4, vec![])]
+    #[case("ab\ncd\nef", 1, vec![0..3, 3..6, 6..8])]
+    #[case("ab\ncd\nef", 4, vec![0..6, 6..8])]
+    #[case("abcdef\n", 2, vec![0..7])]
+    #[case("a\n\n\n", 2, vec![0..2, 2..4])]
+    fn test_split_chunks(
+        #[case] input: &str,
+        #[case] chunk_size: usize,
+        #[case] expected: Vec<std::ops::Range<usize>>,
+    ) {
+        assert_eq!(split_chunks(input, chunk_size), expected);
+    }
+
+    #[rstest]
+    #[case(1, 1)]
+    #[case(4, 7)]
+    #[case(3, 100)]
+    #[case(16, 64 * 1024)]
+    fn test_search(#[case] threads: usize, #[case] chunk_size: usize) {
+        let input = (0..500)
+            .map(|i| match i % 7 {
+                0 => format!("line {i}: error at x\r"),
+                3 => format!("{i} errors\u{3042}"),
+                _ => format!("ok {i}"),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let regex = Regex::new("error(s| at)").unwrap();
+
+        let expected = input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| regex.is_match(line, true).unwrap())
+            .map(|(i, line)| {
+                let start = line.as_ptr() as usize - input.as_ptr() as usize;
+                LineMatch {
+                    line_number: i + 1,
+                    range: start..start + line.len(),
+                }
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expected.len(), 143);
+
+        for is_depth in [true, false] {
+            let matches = ParallelSearch::new(&regex)
+                .threads(threads)
+                .chunk_size(chunk_size)
+                .depth_first(is_depth)
+                .search(&input)
+                .unwrap();
+            assert_eq!(matches, expected);
+        }
+    }
+
+    #[test]
+    fn test_search_error() {
+        let regex = Regex::new("(a)\\1").unwrap();
+        let res = ParallelSearch::new(&regex)
+            .threads(2)
+            .chunk_size(1)
+            .depth_first(false)
+            .search("aa\nb\n");
+        assert!(matches!(
+            res,
+            Err(RegexError::Eval(EvalError::DepthFirstOnly(_)))
+        ));
+    }
+}
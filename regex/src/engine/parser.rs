@@ -1,6 +1,11 @@
-use std::{error::Error, fmt::Display, mem::take};
+use std::{error::Error, fmt::Display, iter::Peekable, mem::take, ops::Range};
 
-#[derive(Debug)]
+use super::{
+    class::{posix_class, CharClass},
+    unicode,
+};
+
+#[derive(Debug, Clone)]
 pub enum AST {
     Char(char),
     Plus(Box<AST>),
@@ -8,130 +13,584 @@ pub enum AST {
     Question(Box<AST>),
     Or(Box<AST>, Box<AST>),
     Seq(Vec<AST>),
+    /// `e{min,max}`, where no maximum means unbounded
+    Repeat(Box<AST>, usize, Option<usize>),
+    Class(CharClass),
+    /// capture group numbered from 1 in order of its left parenthesis
+    Capture(usize, Box<AST>),
+    Backref(usize),
+    /// `(?=e)`
+    LookAhead(Box<AST>),
+    /// `(?!e)`
+    NegLookAhead(Box<AST>),
+    /// `(?<=e)`
+    LookBehind(Box<AST>),
+    /// `(?<!e)`
+    NegLookBehind(Box<AST>),
+    /// `(?>e)`, `e*+`, `e++` and `e?+`
+    Atomic(Box<AST>),
     Period,
+    /// `.` matching an extended grapheme cluster
+    Grapheme,
     Caret,
     Dollar,
 }
 
-#[derive(Debug)]
-pub enum ParseError {
-    InvalidEscape(usize, char),
-    InvalidRightParen(usize),
-    NoPrev(usize),
+/// Machine-readable reason of a `ParseError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    InvalidEscape(char),
+    TrailingBackslash,
+    InvalidRightParen,
+    NoPrev,
     NoRightParen,
+    InvalidBackref(usize),
+    InvalidGroup,
+    NoRightBracket,
+    NoRightBrace,
+    InvalidClass,
+    InvalidRange,
+    InvalidRepeat,
+    InvalidProperty,
     Empty,
 }
 
-impl Display for ParseError {
+impl Display for ParseErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseError::InvalidEscape(pos, c) => {
-                write!(f, "invalid escape: pos = {pos}, char = '{c}'")
-            }
-            ParseError::InvalidRightParen(pos) => {
-                write!(f, "invalid right parenthesis: pos = {pos}")
-            }
-            ParseError::NoPrev(pos) => {
-                write!(f, "no previous expression: pos = {pos}")
-            }
-            ParseError::NoRightParen => {
-                write!(f, "no right parenthesis")
-            }
-            ParseError::Empty => {
-                write!(f, "empty expression")
+            ParseErrorKind::InvalidEscape(c) => write!(f, "invalid escape: char = '{c}'"),
+            ParseErrorKind::TrailingBackslash => write!(f, "trailing backslash"),
+            ParseErrorKind::InvalidRightParen => write!(f, "invalid right parenthesis"),
+            ParseErrorKind::NoPrev => write!(f, "no previous expression"),
+            ParseErrorKind::NoRightParen => write!(f, "no right parenthesis"),
+            ParseErrorKind::InvalidBackref(n) => {
+                write!(f, "reference to undefined group: group = {n}")
             }
+            ParseErrorKind::InvalidGroup => write!(f, "invalid group syntax"),
+            ParseErrorKind::NoRightBracket => write!(f, "no right bracket"),
+            ParseErrorKind::NoRightBrace => write!(f, "no right brace"),
+            ParseErrorKind::InvalidClass => write!(f, "invalid character class"),
+            ParseErrorKind::InvalidRange => write!(f, "invalid range in bracket expression"),
+            ParseErrorKind::InvalidRepeat => write!(f, "invalid repetition count"),
+            ParseErrorKind::InvalidProperty => write!(f, "unknown Unicode property"),
+            ParseErrorKind::Empty => write!(f, "empty expression"),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// byte range of the offending part of the pattern
+    pub span: Range<usize>,
+    pattern: String,
+}
+
+impl ParseError {
+    pub(super) fn new(kind: ParseErrorKind, span: Range<usize>, pattern: &str) -> ParseError {
+        ParseError {
+            kind,
+            span,
+            pattern: pattern.to_string(),
+        }
+    }
+
+    /// Renders the line of the pattern containing the error with carets under
+    /// the offending part.
+    ///
+    /// ```text
+    /// error: no previous expression
+    ///   |
+    ///   | a|*b
+    ///   |   ^
+    /// ```
+    pub fn diagnostic(&self) -> String {
+        let line_start = self.pattern[..self.span.start]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = self.pattern[line_start..]
+            .find('\n')
+            .map_or(self.pattern.len(), |i| line_start + i);
+        let line = &self.pattern[line_start..line_end];
+        let column = self.pattern[line_start..self.span.start].chars().count();
+        let width = self.pattern[self.span.start..self.span.end.min(line_end)]
+            .chars()
+            .count()
+            .max(1);
+        format!(
+            "error: {}\n  |\n  | {}\n  | {}{}\n",
+            self.kind,
+            line,
+            " ".repeat(column),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: pos = {}", self.kind, self.span.start)
+    }
+}
+
 impl Error for ParseError {}
 
+/// Joins the alternatives right-nested, so that the leftmost one is tried
+/// first and has the highest priority when captures are reported.
 fn fold_or(or_seq: Vec<AST>) -> Option<AST> {
     or_seq
         .into_iter()
         .rev()
-        .reduce(|a, b| AST::Or(Box::new(a), Box::new(b)))
+        .reduce(|acc, e| AST::Or(Box::new(e), Box::new(acc)))
+}
+
+enum Group {
+    Capture(usize),
+    LookAhead,
+    NegLookAhead,
+    LookBehind,
+    NegLookBehind,
+    Atomic,
+}
+
+impl Group {
+    fn wrap(self, ast: AST) -> AST {
+        let ast = Box::new(ast);
+        match self {
+            Group::Capture(n) => AST::Capture(n, ast),
+            Group::LookAhead => AST::LookAhead(ast),
+            Group::NegLookAhead => AST::NegLookAhead(ast),
+            Group::LookBehind => AST::LookBehind(ast),
+            Group::NegLookBehind => AST::NegLookBehind(ast),
+            Group::Atomic => AST::Atomic(ast),
+        }
+    }
+}
+
+/// Parses the rest of `(?...` after the question mark. `pos` is the byte
+/// offset of the left parenthesis. Returns `None` for the `(?x)` flag.
+fn parse_group_ext<I>(
+    chars: &mut Peekable<I>,
+    pos: usize,
+    expr: &str,
+) -> Result<Option<Group>, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let invalid = |end: Option<(usize, char)>| {
+        let end = end.map_or(expr.len(), |(i, c)| i + c.len_utf8());
+        Err(ParseError::new(
+            ParseErrorKind::InvalidGroup,
+            pos..end,
+            expr,
+        ))
+    };
+    match chars.next() {
+        Some((_, '=')) => Ok(Some(Group::LookAhead)),
+        Some((_, '!')) => Ok(Some(Group::NegLookAhead)),
+        Some((_, '>')) => Ok(Some(Group::Atomic)),
+        Some((_, '<')) => match chars.next() {
+            Some((_, '=')) => Ok(Some(Group::LookBehind)),
+            Some((_, '!')) => Ok(Some(Group::NegLookBehind)),
+            next => invalid(next),
+        },
+        Some((_, 'x')) => match chars.next() {
+            Some((_, ')')) => Ok(None),
+            next => invalid(next),
+        },
+        next => invalid(next),
+    }
+}
+
+/// Dialect of the pattern syntax.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Syntax {
+    /// Perl style syntax with lookaround, atomic groups and the `(?x)` flag.
+    /// `[` starts a bracket expression and `{m,n}` is an interval, so they
+    /// must be escaped to match literally. A `{` not followed by an interval
+    /// and an unmatched `]` or `}` stay literal.
+    #[default]
+    Perl,
+    /// POSIX basic regular expressions, where `\(`, `\)`, `\{`, `\}`, `\|`,
+    /// `\+` and `\?` are the operators and the unescaped chars are literal
+    PosixBasic,
+    /// POSIX extended regular expressions
+    PosixExtended,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// ignore unescaped whitespace and `#` comments, like `(?x)`
+    pub verbose: bool,
+    pub syntax: Syntax,
+    /// `.` matches an extended grapheme cluster instead of a char
+    pub grapheme: bool,
+}
+
+/// Parses the name after `\p` or `\P` at byte offset `pos`, either a single
+/// letter or a name in braces, and returns the class it stands for.
+fn parse_property<I>(
+    chars: &mut Peekable<I>,
+    pos: usize,
+    expr: &str,
+    is_negated: bool,
+) -> Result<CharClass, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let (name, end) = match chars.next() {
+        Some((i, '{')) => {
+            let len = expr[i + 1..]
+                .find('}')
+                .ok_or_else(|| ParseError::new(ParseErrorKind::NoRightBrace, i..i + 1, expr))?;
+            let end = i + 1 + len;
+            while chars.next_if(|(j, _)| *j <= end).is_some() {}
+            (&expr[i + 1..end], end + 1)
+        }
+        Some((i, c)) => (&expr[i..i + c.len_utf8()], i + c.len_utf8()),
+        None => {
+            return Err(ParseError::new(
+                ParseErrorKind::InvalidProperty,
+                pos..expr.len(),
+                expr,
+            ))
+        }
+    };
+    let class = unicode::property_class(name)
+        .ok_or_else(|| ParseError::new(ParseErrorKind::InvalidProperty, pos..end, expr))?;
+    Ok(if is_negated { class.negate() } else { class })
+}
+
+/// Parses `m}`, `m,}` or `m,n}` followed by `close` at the start of `rest`.
+/// Returns the bounds and the length of the parsed text.
+fn parse_interval(rest: &str, close: &str) -> Option<(usize, Option<usize>, usize)> {
+    let end = rest.find(close)?;
+    let (min, max) = match rest[..end].split_once(',') {
+        Some((min, "")) => (min.parse().ok()?, None),
+        Some((min, max)) => (min.parse().ok()?, Some(max.parse().ok()?)),
+        None => {
+            let n = rest[..end].parse().ok()?;
+            (n, Some(n))
+        }
+    };
+    if !rest[..end].bytes().all(|b| b.is_ascii_digit() || b == b',') {
+        return None;
+    }
+    Some((min, max, end + close.len()))
+}
+
+/// Parses a bracket expression such as `[^a-z[:digit:]]` after the left
+/// bracket at byte offset `pos`.
+fn parse_bracket<I>(
+    chars: &mut Peekable<I>,
+    pos: usize,
+    expr: &str,
+    syntax: Syntax,
+) -> Result<CharClass, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let is_negated = chars.next_if(|(_, c)| *c == '^').is_some();
+    let class = parse_bracket_items(chars, pos, expr, syntax)?;
+    Ok(if is_negated { class.negate() } else { class })
+}
+
+/// Parses the items of a bracket expression up to and including the right
+/// bracket, leaving any negation to the caller.
+pub fn parse_bracket_items<I>(
+    chars: &mut Peekable<I>,
+    pos: usize,
+    expr: &str,
+    syntax: Syntax,
+) -> Result<CharClass, ParseError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let unclosed = || ParseError::new(ParseErrorKind::NoRightBracket, pos..pos + 1, expr);
+    // only the chars special in brackets can be escaped
+    let escaped = |c, span| match c {
+        '\\' | ']' | '[' | '-' | '^' => Ok(c),
+        _ => Err(ParseError::new(ParseErrorKind::InvalidEscape(c), span, expr)),
+    };
+    let mut ranges = Vec::new();
+    let mut is_first = true;
+
+    loop {
+        let (i, c) = chars.next().ok_or_else(unclosed)?;
+        let lo = match c {
+            ']' if !is_first => break,
+            '[' if matches!(chars.peek(), Some((_, ':' | '=' | '.'))) => {
+                let (j, delim) = chars.next().ok_or_else(unclosed)?;
+                let name_start = j + 1;
+                let name_len = expr[name_start..]
+                    .find(&format!("{delim}]"))
+                    .ok_or_else(unclosed)?;
+                let end = name_start + name_len + 2;
+                while chars.next_if(|(k, _)| *k < end).is_some() {}
+
+                let name = &expr[name_start..name_start + name_len];
+                let invalid = || ParseError::new(ParseErrorKind::InvalidClass, i..end, expr);
+                if delim == ':' {
+                    let class = posix_class(name).ok_or_else(invalid)?;
+                    ranges.extend_from_slice(class.ranges());
+                    is_first = false;
+                    continue;
+                }
+                // `[=c=]` and `[.c.]` stand for the single char c
+                let mut name_chars = name.chars();
+                match (name_chars.next(), name_chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(invalid()),
+                }
+            }
+            '\\' if syntax == Syntax::Perl => match chars.next().ok_or_else(unclosed)? {
+                (_, c @ ('p' | 'P')) => {
+                    let class = parse_property(chars, i, expr, c == 'P')?;
+                    ranges.extend_from_slice(class.ranges());
+                    is_first = false;
+                    continue;
+                }
+                (j, c) => escaped(c, i..j + c.len_utf8())?,
+            },
+            _ => c,
+        };
+        is_first = false;
+
+        let hi = match chars.peek() {
+            Some((j, '-')) if !expr[j + 1..].starts_with(']') => {
+                chars.next();
+                let (k, hi) = chars.next().ok_or_else(unclosed)?;
+                let (k, hi) = match hi {
+                    '\\' if syntax == Syntax::Perl => {
+                        let (l, hi) = chars.next().ok_or_else(unclosed)?;
+                        (l, escaped(hi, k..l + hi.len_utf8())?)
+                    }
+                    _ => (k, hi),
+                };
+                if hi < lo {
+                    return Err(ParseError::new(
+                        ParseErrorKind::InvalidRange,
+                        i..k + hi.len_utf8(),
+                        expr,
+                    ));
+                }
+                hi
+            }
+            _ => lo,
+        };
+        ranges.push((lo, hi));
+    }
+
+    Ok(CharClass::new(ranges))
 }
 
 pub fn parse(expr: &str) -> Result<AST, ParseError> {
+    parse_with(expr, &ParseOptions::default())
+}
+
+pub fn parse_with(expr: &str, options: &ParseOptions) -> Result<AST, ParseError> {
     #[derive(Default)]
     struct State {
-        escape: bool,
+        /// byte offset of the backslash starting an escape sequence
+        escape: Option<usize>,
+        /// the last expression in `ast_seq` is a quantifier
+        quantified: bool,
+        /// whitespace and comments are ignored until the end of the group
+        verbose: bool,
         ast_seq: Vec<AST>,
         or_seq: Vec<AST>,
     }
-    let mut state: State = State::default();
+    let mut state: State = State {
+        verbose: options.verbose,
+        ..Default::default()
+    };
     let mut state_stack = Vec::new();
+    let mut group_count = 0;
+    let mut backrefs = Vec::new();
+    let syntax = options.syntax;
+
+    let error = |kind, span| ParseError::new(kind, span, expr);
+
+    let mut chars = expr.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if state.verbose && state.escape.is_none() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == '#' {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                continue;
+            }
+        }
 
-    for (i, c) in expr.chars().enumerate() {
-        if state.escape {
-            match c {
-                '\\' | '(' | ')' | '|' | '+' | '*' | '?' | '.' | '^' | '$' => {
-                    state.ast_seq.push(AST::Char(c))
+        let quantified = take(&mut state.quantified);
+        let mut span = i..i + c.len_utf8();
+        let rest = &expr[span.end..];
+        let is_operator = match take(&mut state.escape) {
+            Some(escape) => {
+                span.start = escape;
+                match c {
+                    '1'..='9' => {
+                        let n = c as usize - '0' as usize;
+                        backrefs.push((span, n));
+                        state.ast_seq.push(AST::Backref(n));
+                        continue;
+                    }
+                    'p' | 'P' if syntax == Syntax::Perl => {
+                        let class = parse_property(&mut chars, escape, expr, c == 'P')?;
+                        state.ast_seq.push(AST::Class(class));
+                        continue;
+                    }
+                    '(' | ')' | '{' | '}' | '|' | '+' | '?' if syntax == Syntax::PosixBasic => true,
+                    '\\' | '(' | ')' | '|' | '+' | '*' | '?' | '.' | '^' | '$' | '[' | ']'
+                    | '{' | '}' | ' ' | '#' => false,
+                    _ => return Err(error(ParseErrorKind::InvalidEscape(c), span)),
                 }
-                _ => return Err(ParseError::InvalidEscape(i, c)),
             }
-            state.escape = false;
-        } else {
-            match c {
-                '+' => match state.ast_seq.pop() {
-                    Some(prev) => state.ast_seq.push(AST::Plus(Box::new(prev))),
-                    None => return Err(ParseError::NoPrev(i)),
-                },
-                '*' => match state.ast_seq.pop() {
-                    Some(prev) => state.ast_seq.push(AST::Star(Box::new(prev))),
-                    None => return Err(ParseError::NoPrev(i)),
-                },
-                '?' => match state.ast_seq.pop() {
-                    Some(prev) => state.ast_seq.push(AST::Question(Box::new(prev))),
-                    None => return Err(ParseError::NoPrev(i)),
-                },
-                '(' => {
-                    state_stack.push(take(&mut state));
+            None => match (c, syntax) {
+                ('\\', _) => {
+                    state.escape = Some(i);
+                    continue;
+                }
+                ('.' | '[', _) => true,
+                ('*', Syntax::PosixBasic) => {
+                    !matches!(state.ast_seq.last(), None | Some(AST::Caret))
+                }
+                ('^', Syntax::PosixBasic) => state.ast_seq.is_empty(),
+                ('$', Syntax::PosixBasic) => {
+                    rest.is_empty() || rest.starts_with("\\)") || rest.starts_with("\\|")
+                }
+                (_, Syntax::PosixBasic) => false,
+                ('(' | ')' | '|' | '+' | '*' | '?' | '^' | '$' | '{', _) => true,
+                _ => false,
+            },
+        };
+
+        if !is_operator {
+            state.ast_seq.push(AST::Char(c));
+            continue;
+        }
+
+        match c {
+            '+' if quantified && syntax == Syntax::Perl => match state.ast_seq.pop() {
+                Some(prev) => state.ast_seq.push(AST::Atomic(Box::new(prev))),
+                None => return Err(error(ParseErrorKind::NoPrev, span)),
+            },
+            '+' => match state.ast_seq.pop() {
+                Some(prev) => {
+                    state.ast_seq.push(AST::Plus(Box::new(prev)));
+                    state.quantified = true;
                 }
-                ')' => match state_stack.pop() {
-                    Some(mut parent_state) => {
-                        if !state.ast_seq.is_empty() {
-                            state.or_seq.push(AST::Seq(state.ast_seq));
+                None => return Err(error(ParseErrorKind::NoPrev, span)),
+            },
+            '*' => match state.ast_seq.pop() {
+                Some(prev) => {
+                    state.ast_seq.push(AST::Star(Box::new(prev)));
+                    state.quantified = true;
+                }
+                None => return Err(error(ParseErrorKind::NoPrev, span)),
+            },
+            '?' => match state.ast_seq.pop() {
+                Some(prev) => {
+                    state.ast_seq.push(AST::Question(Box::new(prev)));
+                    state.quantified = true;
+                }
+                None => return Err(error(ParseErrorKind::NoPrev, span)),
+            },
+            '{' => {
+                let close = match syntax {
+                    Syntax::PosixBasic => "\\}",
+                    _ => "}",
+                };
+                match parse_interval(rest, close) {
+                    Some((min, max, len)) => {
+                        let end = span.end + len;
+                        if max.is_some_and(|max| max < min) {
+                            return Err(error(ParseErrorKind::InvalidRepeat, span.start..end));
                         }
-                        match fold_or(state.or_seq) {
-                            Some(ast) => {
-                                parent_state.ast_seq.push(ast);
+                        while chars.next_if(|(j, _)| *j < end).is_some() {}
+                        match state.ast_seq.pop() {
+                            Some(prev) => {
+                                state.ast_seq.push(AST::Repeat(Box::new(prev), min, max));
+                                state.quantified = true;
                             }
-                            None => {}
+                            None => return Err(error(ParseErrorKind::NoPrev, span)),
                         }
-                        state = parent_state;
                     }
-                    None => return Err(ParseError::InvalidRightParen(i)),
-                },
-                '|' => {
-                    if state.ast_seq.is_empty() {
-                        return Err(ParseError::NoPrev(i));
-                    } else {
-                        state.or_seq.push(AST::Seq(take(&mut state.ast_seq)))
+                    None if syntax == Syntax::PosixBasic => {
+                        return Err(error(ParseErrorKind::InvalidRepeat, span))
                     }
+                    None => state.ast_seq.push(AST::Char(c)),
                 }
-                '\\' => {
-                    state.escape = true;
-                }
-                '.' => {
-                    state.ast_seq.push(AST::Period);
-                }
-                '^' => {
-                    state.ast_seq.push(AST::Caret);
-                }
-                '$' => {
-                    state.ast_seq.push(AST::Dollar);
+            }
+            '[' => {
+                let class = parse_bracket(&mut chars, i, expr, syntax)?;
+                state.ast_seq.push(AST::Class(class));
+            }
+            '(' => {
+                let group = match chars.next_if(|(_, c)| *c == '?' && syntax == Syntax::Perl) {
+                    Some(_) => match parse_group_ext(&mut chars, i, expr)? {
+                        Some(group) => group,
+                        None => {
+                            state.verbose = true;
+                            continue;
+                        }
+                    },
+                    None => {
+                        group_count += 1;
+                        Group::Capture(group_count)
+                    }
+                };
+                let verbose = state.verbose;
+                state_stack.push((take(&mut state), group, i));
+                state.verbose = verbose;
+            }
+            ')' => match state_stack.pop() {
+                Some((mut parent_state, group, _)) => {
+                    if !state.ast_seq.is_empty() {
+                        state.or_seq.push(AST::Seq(state.ast_seq));
+                    }
+                    let ast = fold_or(state.or_seq).unwrap_or(AST::Seq(Vec::new()));
+                    parent_state.ast_seq.push(group.wrap(ast));
+                    state = parent_state;
                 }
-                _ => {
-                    state.ast_seq.push(AST::Char(c));
+                None => return Err(error(ParseErrorKind::InvalidRightParen, span)),
+            },
+            '|' => {
+                if state.ast_seq.is_empty() {
+                    return Err(error(ParseErrorKind::NoPrev, span));
+                } else {
+                    state.or_seq.push(AST::Seq(take(&mut state.ast_seq)))
                 }
             }
+            '.' if options.grapheme => {
+                state.ast_seq.push(AST::Grapheme);
+            }
+            '.' => {
+                state.ast_seq.push(AST::Period);
+            }
+            '^' => {
+                state.ast_seq.push(AST::Caret);
+            }
+            '$' => {
+                state.ast_seq.push(AST::Dollar);
+            }
+            _ => {
+                state.ast_seq.push(AST::Char(c));
+            }
         }
     }
 
-    if !state_stack.is_empty() {
-        return Err(ParseError::NoRightParen);
+    if let Some(escape) = state.escape {
+        return Err(error(ParseErrorKind::TrailingBackslash, escape..expr.len()));
+    }
+
+    if let Some((_, _, pos)) = state_stack.pop() {
+        return Err(error(ParseErrorKind::NoRightParen, pos..pos + 1));
+    }
+
+    if let Some((span, n)) = backrefs.into_iter().find(|(_, n)| *n > group_count) {
+        return Err(error(ParseErrorKind::InvalidBackref(n), span));
     }
 
     if !state.ast_seq.is_empty() {
@@ -140,6 +599,6 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
 
     match fold_or(state.or_seq) {
         Some(ast) => Ok(ast),
-        None => Err(ParseError::Empty),
+        None => Err(error(ParseErrorKind::Empty, 0..expr.len())),
     }
 }
@@ -0,0 +1,390 @@
+//! Generation of strings matched by a compiled expression from the first char
+//! to the last.
+
+use std::collections::VecDeque;
+
+use super::{
+    class::CharClass,
+    codegen::Instruction,
+    dfa::{Alphabet, Dfa, Mode},
+    evaluator, Regex, RegexError,
+};
+
+/// Iterator over the strings matched by an expression, shortest first. Chars
+/// that the expression does not distinguish are represented by one of them.
+pub struct Enumerator {
+    alphabet: Alphabet,
+    dfa: Dfa,
+    live: Vec<bool>,
+    queue: VecDeque<(usize, String)>,
+}
+
+impl Enumerator {
+    pub fn new(regex: &Regex) -> Result<Enumerator, RegexError> {
+        let alphabet = Alphabet::new(&[&regex.code]);
+        let dfa = Dfa::new(&regex.code, &alphabet, Mode::Full)?;
+        let live = dfa.live_states();
+        let queue = if live[0] {
+            VecDeque::from([(0, String::new())])
+        } else {
+            VecDeque::new()
+        };
+        Ok(Enumerator {
+            alphabet,
+            dfa,
+            live,
+            queue,
+        })
+    }
+}
+
+impl Iterator for Enumerator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (state, s) = self.queue.pop_front()?;
+            for (class, next) in self.dfa.trans[state].iter().enumerate() {
+                if self.live[*next] {
+                    let mut s = s.clone();
+                    s.push(self.alphabet.representative(class));
+                    self.queue.push_back((*next, s));
+                }
+            }
+            if self.dfa.accepting[state] {
+                return Some(s);
+            }
+        }
+    }
+}
+
+/// chars produced by `period`
+const ANY_CHARS: &[char] = &[
+    'a', 'b', 'c', 'x', 'y', 'z', 'A', 'B', 'Z', '0', '1', '9', ' ', '-', '_', '.',
+];
+
+/// instructions followed by one walk before it is abandoned, which ends the
+/// walks of programs looping without a `split`
+const MAX_STEPS: usize = 1 << 20;
+
+/// Generates random strings by walking the program with a seeded generator.
+pub struct Sampler<'a> {
+    regex: &'a Regex,
+    rng: XorShift,
+    max_repeat: usize,
+    max_tries: usize,
+    is_near_miss: bool,
+}
+
+impl<'a> Sampler<'a> {
+    pub fn new(regex: &'a Regex, seed: u64) -> Sampler<'a> {
+        Sampler {
+            regex,
+            rng: XorShift::new(seed),
+            max_repeat: 3,
+            max_tries: 100,
+            is_near_miss: false,
+        }
+    }
+
+    /// Limits how many times each loop is repeated in one string.
+    pub fn max_repeat(mut self, n: usize) -> Self {
+        self.max_repeat = n;
+        self
+    }
+
+    /// Limits the attempts made by one call of `sample`.
+    pub fn max_tries(mut self, n: usize) -> Self {
+        self.max_tries = n;
+        self
+    }
+
+    /// Produces strings one edit away from a match in which `is_match` finds
+    /// no match anywhere. Expressions matching every string, such as `a*`,
+    /// have none.
+    pub fn near_miss(mut self, is_near_miss: bool) -> Self {
+        self.is_near_miss = is_near_miss;
+        self
+    }
+
+    /// Returns `None` if no string was found within the attempts.
+    pub fn sample(&mut self) -> Result<Option<String>, RegexError> {
+        for _ in 0..self.max_tries {
+            let mut line = match self.walk() {
+                Some(line) => line,
+                None => continue,
+            };
+            if self.is_near_miss {
+                self.mutate(&mut line);
+            }
+            let is_match = if self.is_near_miss {
+                evaluator::eval(&self.regex.code, &line, true)?
+            } else {
+                evaluator::eval_full(&self.regex.code, &line)?
+            };
+            if is_match != self.is_near_miss {
+                return Ok(Some(line.into_iter().collect()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Follows one random path to `match`. Once a `split` has been taken
+    /// `max_repeat` times its second branch, which leaves loops, is forced.
+    /// The result may still fail the assertions on the path. Returns `None`
+    /// after `MAX_STEPS` instructions.
+    fn walk(&mut self) -> Option<Vec<char>> {
+        let code = &self.regex.code;
+        let mut line = Vec::new();
+        let mut caps: Vec<Option<usize>> = Vec::new();
+        let mut counts = vec![0; code.len()];
+        let mut counters = Vec::new();
+        let mut pc = 0;
+        let mut tail = None;
+        // where to continue after the `match` ending an atomic group
+        let mut returns = Vec::new();
+
+        for _ in 0..MAX_STEPS {
+            match code.get(pc)? {
+                Instruction::Char(c) => {
+                    line.push(*c);
+                    pc += 1;
+                }
+                Instruction::Class(class) => {
+                    line.push(self.class_char(class)?);
+                    pc += 1;
+                }
+                Instruction::AnyChar | Instruction::AnyGrapheme => {
+                    line.push(ANY_CHARS[self.rng.below(ANY_CHARS.len())]);
+                    pc += 1;
+                }
+                Instruction::Match => match returns.pop() {
+                    Some(addr) => pc = addr,
+                    None => {
+                        return match tail {
+                            Some(len) if len != line.len() => None,
+                            _ => Some(line),
+                        };
+                    }
+                },
+                Instruction::Jump(addr) => pc = *addr,
+                Instruction::Split(addr1, addr2) => {
+                    counts[pc] += 1;
+                    pc = if counts[pc] <= self.max_repeat && self.rng.below(2) == 0 {
+                        *addr1
+                    } else {
+                        *addr2
+                    };
+                }
+                Instruction::AssertHead => {
+                    if !line.is_empty() {
+                        return None;
+                    }
+                    pc += 1;
+                }
+                Instruction::AssertTail => {
+                    tail.get_or_insert(line.len());
+                    pc += 1;
+                }
+                Instruction::Save(slot) => {
+                    if caps.len() <= *slot {
+                        caps.resize(slot + 1, None);
+                    }
+                    caps[*slot] = Some(line.len());
+                    pc += 1;
+                }
+                Instruction::Backref(n) => {
+                    if let (Some(Some(start)), Some(Some(end))) =
+                        (caps.get(2 * n), caps.get(2 * n + 1))
+                    {
+                        let group = line.get(*start..*end)?.to_vec();
+                        line.extend(group);
+                    }
+                    pc += 1;
+                }
+                // the sub-program is checked by the evaluator afterwards
+                Instruction::LookAhead(addr)
+                | Instruction::NegLookAhead(addr)
+                | Instruction::LookBehind(_, _, addr)
+                | Instruction::NegLookBehind(_, _, addr) => pc = *addr,
+                Instruction::Atomic(addr) => {
+                    returns.push(*addr);
+                    pc += 1;
+                }
+                Instruction::CounterReset(n) => {
+                    if counters.len() <= *n {
+                        counters.resize(n + 1, 0);
+                    }
+                    counters[*n] = 0;
+                    pc += 1;
+                }
+                Instruction::CounterInc(n) => {
+                    if counters.len() <= *n {
+                        counters.resize(n + 1, 0);
+                    }
+                    counters[*n] += 1;
+                    pc += 1;
+                }
+                Instruction::CounterCheck(n, min, max, addr) => {
+                    let count = counters.get(*n).copied().unwrap_or(0);
+                    pc = if count < *min || (count < *max && self.rng.below(2) == 0) {
+                        pc + 1
+                    } else {
+                        *addr
+                    };
+                }
+            }
+        }
+        None
+    }
+
+    /// Picks a char of the class, preferring the ones `period` produces.
+    /// Returns `None` for an empty class.
+    fn class_char(&mut self, class: &CharClass) -> Option<char> {
+        let readable = ANY_CHARS
+            .iter()
+            .filter(|c| class.contains(**c))
+            .collect::<Vec<_>>();
+        if !readable.is_empty() && self.rng.below(4) != 0 {
+            return Some(*readable[self.rng.below(readable.len())]);
+        }
+        let ranges = class.ranges();
+        if ranges.is_empty() {
+            return None;
+        }
+        let (lo, hi) = ranges[self.rng.below(ranges.len())];
+        let offset = self.rng.below(hi as usize - lo as usize + 1) as u32;
+        Some(char::from_u32(lo as u32 + offset).unwrap_or(lo))
+    }
+
+    /// Inserts, deletes or substitutes one char.
+    fn mutate(&mut self, line: &mut Vec<char>) {
+        let c = ANY_CHARS[self.rng.below(ANY_CHARS.len())];
+        match (self.rng.below(3), line.is_empty()) {
+            (0, _) | (_, true) => line.insert(self.rng.below(line.len() + 1), c),
+            (1, false) => {
+                line.remove(self.rng.below(line.len()));
+            }
+            _ => {
+                let i = self.rng.below(line.len());
+                line[i] = c;
+            }
+        }
+    }
+}
+
+/// xorshift64* pseudo random number generator
+pub(super) struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub(super) fn new(seed: u64) -> XorShift {
+        XorShift {
+            // the state must not be zero
+            state: (seed ^ 0x9E37_79B9_7F4A_7C15).max(1),
+        }
+    }
+
+    pub(super) fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in `0..n`.
+    pub(super) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Enumerator, Sampler};
+    use crate::engine::{codegen::Instruction, evaluator, MatchKind, Regex};
+    use rstest::*;
+
+    #[rstest]
+    #[case("a(b|cd)*", vec!["a", "ab", "abb", "acd", "abbb"])]
+    #[case("^x?$", vec!["", "x"])]
+    #[case("ab|a.", vec!["aA", "aa", "ab", "ac"])]
+    #[case("^b{20}$", vec!["bbbbbbbbbbbbbbbbbbbb"])]
+    fn test_enumerate(#[case] expr: &str, #[case] expected: Vec<&str>) {
+        let regex = Regex::new(expr).unwrap();
+        let samples = Enumerator::new(&regex).unwrap().take(5).collect::<Vec<_>>();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn test_enumerate_counter() {
+        let regex = Regex::new("^b{1001}c?$").unwrap();
+        let samples = Enumerator::new(&regex).unwrap().collect::<Vec<_>>();
+        assert_eq!(samples, vec!["b".repeat(1001), "b".repeat(1001) + "c"]);
+    }
+
+    #[rstest]
+    #[case("(ab|c)+d?")]
+    #[case("^(a+)b\\1$")]
+    #[case("x(?=y)y.*")]
+    #[case("(a|b)*(?<!b)")]
+    #[case("(?>ab|a)+c")]
+    #[case("^[[:digit:]]{2,3}[^a-z]$")]
+    fn test_sample(#[case] expr: &str) {
+        let regex = Regex::new(expr).unwrap();
+        let mut sampler = Sampler::new(&regex, 42).max_repeat(4);
+        for _ in 0..20 {
+            let s = sampler.sample().unwrap().unwrap();
+            assert!(regex.is_match(&s, true).unwrap(), "{s}");
+            let line = s.chars().collect::<Vec<char>>();
+            assert!(evaluator::eval_full(&regex.code, &line).unwrap(), "{s}");
+        }
+    }
+
+    #[rstest]
+    #[case("ab")]
+    #[case("(ab|c)+d?")]
+    #[case("^(a+)b\\1$")]
+    #[case("x(?=y)y.*")]
+    #[case("(?>ab|a)+c")]
+    #[case("^[[:digit:]]{2,3}[^a-z]$")]
+    fn test_near_miss(#[case] expr: &str) {
+        let regex = Regex::new(expr).unwrap();
+        for seed in 0..40 {
+            let mut near_miss = Sampler::new(&regex, seed).near_miss(true);
+            let s = near_miss.sample().unwrap().unwrap();
+            assert!(!regex.is_match(&s, true).unwrap(), "{s}");
+        }
+    }
+
+    #[rstest]
+    #[case("a*")]
+    #[case("(a|b)*(?<!b)")]
+    fn test_near_miss_none(#[case] expr: &str) {
+        let regex = Regex::new(expr).unwrap();
+        let mut near_miss = Sampler::new(&regex, 42).near_miss(true);
+        assert_eq!(near_miss.sample().unwrap(), None);
+    }
+
+    #[rstest]
+    #[case(vec![Instruction::Jump(0), Instruction::Match])]
+    #[case(vec![Instruction::CounterInc(0), Instruction::CounterCheck(1, 1, 2, 3), Instruction::Match, Instruction::Match])]
+    fn test_unchecked_program(#[case] code: Vec<Instruction>) {
+        let regex = Regex {
+            code,
+            group_count: 0,
+            match_kind: MatchKind::default(),
+            bit_parallel: None,
+        };
+        assert!(Sampler::new(&regex, 42).max_tries(2).sample().is_ok());
+    }
+
+    #[test]
+    fn test_seed() {
+        let regex = Regex::new("(a|b|c)+").unwrap();
+        let first = (0..5)
+            .map(|_| Sampler::new(&regex, 7).sample().unwrap())
+            .collect::<Vec<_>>();
+        assert!(first.windows(2).all(|w| w[0] == w[1]));
+    }
+}
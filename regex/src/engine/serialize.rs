@@ -0,0 +1,707 @@
+//! Binary format of compiled expressions, so that a program can be compiled
+//! once and loaded later without parsing the expression again.
+//!
+//! All integers are little endian. The header is the magic `RZRX`, the format
+//! version as a `u16`, the flags as a `u16`, then the length and the CRC-32 of
+//! the payload as `u32`s. The payload is the number of capture groups and the
+//! number of instructions, followed by each instruction as a tag byte and its
+//! operands: chars as `u32`, addresses, slots and bounds as `u64`, and classes
+//! as a range count followed by the bounds of each range.
+//!
+//! A loaded program runs on the depth-first and breadth-first evaluators
+//! only, since the bit-parallel form is built from the `AST`.
+
+use std::{error::Error, fmt::Display};
+
+use super::{class::CharClass, codegen::Instruction, MatchKind, Regex};
+
+const MAGIC: &[u8; 4] = b"RZRX";
+
+/// version written by `Regex::to_bytes`, and the only one loaded
+pub const FORMAT_VERSION: u16 = 1;
+
+/// flag set for `MatchKind::LeftmostLongest`
+const FLAG_LEFTMOST_LONGEST: u16 = 1;
+
+const HEADER_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownFlags(u16),
+    ChecksumMismatch,
+    /// the data ends in the middle of the program
+    Truncated,
+    /// bytes remain after the last instruction
+    TrailingBytes,
+    UnknownInstruction(u8),
+    /// an instruction jumps outside of the program
+    InvalidTarget {
+        pc: usize,
+        target: usize,
+    },
+    /// an operand is out of range, such as an invalid char or a capture slot
+    /// beyond the group count
+    InvalidOperand(usize),
+    /// the instruction at the address continues past the end of the program
+    FallsOffEnd(usize),
+    /// the group count exceeds the groups saved by the program
+    InvalidGroupCount(usize),
+    /// the instruction at the address is on a loop that neither branches nor
+    /// consumes input, so it never ends
+    EmptyLoop(usize),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::InvalidTarget { pc, target } => {
+                write!(
+                    f,
+                    "LoadError: instruction {pc} jumps to invalid address {target}"
+                )
+            }
+            LoadError::InvalidOperand(pc) => {
+                write!(f, "LoadError: instruction {pc} has an invalid operand")
+            }
+            LoadError::FallsOffEnd(pc) => {
+                write!(f, "LoadError: instruction {pc} continues past the end")
+            }
+            LoadError::InvalidGroupCount(count) => {
+                write!(f, "LoadError: {count} groups exceed the saved groups")
+            }
+            LoadError::EmptyLoop(pc) => {
+                write!(f, "LoadError: instruction {pc} is on an endless loop")
+            }
+            _ => write!(f, "LoadError: {:?}", self),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl Regex {
+    /// Serializes the compiled program, its capture group count and its
+    /// match kind.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        put_usize(&mut payload, self.group_count);
+        put_usize(&mut payload, self.code.len());
+        for inst in self.code.iter() {
+            put_inst(&mut payload, inst);
+        }
+
+        let flags = match self.match_kind {
+            MatchKind::LeftmostFirst => 0,
+            MatchKind::LeftmostLongest => FLAG_LEFTMOST_LONGEST,
+        };
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    /// Loads a program written by `to_bytes`. The program is rejected unless
+    /// every jump stays inside it, every path ends at a `match` or a jump, and
+    /// its capture slots and counters are consistent, so that the evaluators
+    /// never run an invalid program.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Regex, LoadError> {
+        let mut header = Reader { bytes, pos: 0 };
+        if header.take(MAGIC.len())? != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = header.u16()?;
+        if version != FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let flags = header.u16()?;
+        if flags & !FLAG_LEFTMOST_LONGEST != 0 {
+            return Err(LoadError::UnknownFlags(flags));
+        }
+        let len = header.u32()? as usize;
+        let checksum = header.u32()?;
+        let payload = header.take(len)?;
+        if header.pos != bytes.len() {
+            return Err(LoadError::TrailingBytes);
+        }
+        if crc32(payload) != checksum {
+            return Err(LoadError::ChecksumMismatch);
+        }
+
+        let mut reader = Reader {
+            bytes: payload,
+            pos: 0,
+        };
+        let group_count = reader.usize()?;
+        let count = reader.usize()?;
+        // every instruction takes at least one byte, which bounds the
+        // capacity asked by a corrupted count
+        let mut code = Vec::with_capacity(count.min(payload.len()));
+        for _ in 0..count {
+            code.push(reader.inst(code.len())?);
+        }
+        if reader.pos != payload.len() {
+            return Err(LoadError::TrailingBytes);
+        }
+        validate(&code, group_count)?;
+
+        Ok(Regex {
+            code,
+            group_count,
+            match_kind: if flags & FLAG_LEFTMOST_LONGEST != 0 {
+                MatchKind::LeftmostLongest
+            } else {
+                MatchKind::LeftmostFirst
+            },
+            bit_parallel: None,
+        })
+    }
+}
+
+fn put_usize(buf: &mut Vec<u8>, n: usize) {
+    buf.extend_from_slice(&(n as u64).to_le_bytes());
+}
+
+fn put_char(buf: &mut Vec<u8>, c: char) {
+    buf.extend_from_slice(&(c as u32).to_le_bytes());
+}
+
+fn put_inst(buf: &mut Vec<u8>, inst: &Instruction) {
+    match inst {
+        Instruction::Char(c) => {
+            buf.push(0);
+            put_char(buf, *c);
+        }
+        Instruction::Class(class) => {
+            buf.push(1);
+            put_usize(buf, class.ranges().len());
+            for (lo, hi) in class.ranges() {
+                put_char(buf, *lo);
+                put_char(buf, *hi);
+            }
+        }
+        Instruction::Match => buf.push(2),
+        Instruction::Jump(addr) => {
+            buf.push(3);
+            put_usize(buf, *addr);
+        }
+        Instruction::Split(addr1, addr2) => {
+            buf.push(4);
+            put_usize(buf, *addr1);
+            put_usize(buf, *addr2);
+        }
+        Instruction::AnyChar => buf.push(5),
+        Instruction::AnyGrapheme => buf.push(6),
+        Instruction::AssertHead => buf.push(7),
+        Instruction::AssertTail => buf.push(8),
+        Instruction::Save(slot) => {
+            buf.push(9);
+            put_usize(buf, *slot);
+        }
+        Instruction::Backref(n) => {
+            buf.push(10);
+            put_usize(buf, *n);
+        }
+        Instruction::LookAhead(addr) => {
+            buf.push(11);
+            put_usize(buf, *addr);
+        }
+        Instruction::NegLookAhead(addr) => {
+            buf.push(12);
+            put_usize(buf, *addr);
+        }
+        Instruction::LookBehind(min, max, addr) => {
+            buf.push(13);
+            put_usize(buf, *min);
+            put_usize(buf, *max);
+            put_usize(buf, *addr);
+        }
+        Instruction::NegLookBehind(min, max, addr) => {
+            buf.push(14);
+            put_usize(buf, *min);
+            put_usize(buf, *max);
+            put_usize(buf, *addr);
+        }
+        Instruction::Atomic(addr) => {
+            buf.push(15);
+            put_usize(buf, *addr);
+        }
+        Instruction::CounterReset(n) => {
+            buf.push(16);
+            put_usize(buf, *n);
+        }
+        Instruction::CounterInc(n) => {
+            buf.push(17);
+            put_usize(buf, *n);
+        }
+        Instruction::CounterCheck(n, min, max, addr) => {
+            buf.push(18);
+            put_usize(buf, *n);
+            put_usize(buf, *min);
+            put_usize(buf, *max);
+            put_usize(buf, *addr);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(len).ok_or(LoadError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(LoadError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, LoadError> {
+        let n = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        usize::try_from(n).map_err(|_| LoadError::Truncated)
+    }
+
+    fn char(&mut self, pc: usize) -> Result<char, LoadError> {
+        char::from_u32(self.u32()?).ok_or(LoadError::InvalidOperand(pc))
+    }
+
+    /// Reads the instruction at address `pc`.
+    fn inst(&mut self, pc: usize) -> Result<Instruction, LoadError> {
+        let inst = match self.u8()? {
+            0 => Instruction::Char(self.char(pc)?),
+            1 => {
+                let count = self.usize()?;
+                let mut ranges = Vec::with_capacity(count.min(self.bytes.len() / 8));
+                for _ in 0..count {
+                    let (lo, hi) = (self.char(pc)?, self.char(pc)?);
+                    if lo > hi {
+                        return Err(LoadError::InvalidOperand(pc));
+                    }
+                    ranges.push((lo, hi));
+                }
+                Instruction::Class(Box::new(CharClass::new(ranges)))
+            }
+            2 => Instruction::Match,
+            3 => Instruction::Jump(self.usize()?),
+            4 => Instruction::Split(self.usize()?, self.usize()?),
+            5 => Instruction::AnyChar,
+            6 => Instruction::AnyGrapheme,
+            7 => Instruction::AssertHead,
+            8 => Instruction::AssertTail,
+            9 => Instruction::Save(self.usize()?),
+            10 => Instruction::Backref(self.usize()?),
+            11 => Instruction::LookAhead(self.usize()?),
+            12 => Instruction::NegLookAhead(self.usize()?),
+            13 => Instruction::LookBehind(self.usize()?, self.usize()?, self.usize()?),
+            14 => Instruction::NegLookBehind(self.usize()?, self.usize()?, self.usize()?),
+            15 => Instruction::Atomic(self.usize()?),
+            16 => Instruction::CounterReset(self.usize()?),
+            17 => Instruction::CounterInc(self.usize()?),
+            18 => Instruction::CounterCheck(
+                self.usize()?,
+                self.usize()?,
+                self.usize()?,
+                self.usize()?,
+            ),
+            tag => return Err(LoadError::UnknownInstruction(tag)),
+        };
+        Ok(inst)
+    }
+}
+
+/// Checks that the program can be run by the evaluators: jump targets are
+/// addresses of the program, only `match` and `jump` end it, capture slots
+/// and backreferences refer to existing groups, bounds are ordered, every
+/// counter is reset before it is used, and every loop branches or consumes
+/// input.
+fn validate(code: &[Instruction], group_count: usize) -> Result<(), LoadError> {
+    let check_target = |pc: usize, target: usize| {
+        if target < code.len() {
+            Ok(())
+        } else {
+            Err(LoadError::InvalidTarget { pc, target })
+        }
+    };
+    // counters are numbered densely from zero, one reset each, so that the
+    // evaluators never allocate more counters than the program resets
+    let resets = code
+        .iter()
+        .filter(|i| matches!(i, Instruction::CounterReset(_)))
+        .count();
+    let mut is_reset = vec![false; resets];
+    for i in code {
+        if let Instruction::CounterReset(n) = i {
+            if let Some(reset) = is_reset.get_mut(*n) {
+                *reset = true;
+            }
+        }
+    }
+    let is_reset = |n: usize| is_reset.get(n).copied().unwrap_or(false);
+    let max_group = code
+        .iter()
+        .filter_map(|i| match i {
+            Instruction::Save(slot) => Some(slot / 2),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    // the compiler saves both ends of every group, so a group count above
+    // half the number of saves cannot come from it
+    let saves = code
+        .iter()
+        .filter(|i| matches!(i, Instruction::Save(_)))
+        .count();
+
+    if code.is_empty() {
+        return Err(LoadError::FallsOffEnd(0));
+    }
+    if group_count > max_group || group_count > saves / 2 {
+        return Err(LoadError::InvalidGroupCount(group_count));
+    }
+    for (pc, inst) in code.iter().enumerate() {
+        match inst {
+            Instruction::Jump(addr)
+            | Instruction::LookAhead(addr)
+            | Instruction::NegLookAhead(addr)
+            | Instruction::Atomic(addr) => check_target(pc, *addr)?,
+            Instruction::Split(addr1, addr2) => {
+                check_target(pc, *addr1)?;
+                check_target(pc, *addr2)?;
+            }
+            Instruction::LookBehind(min, max, addr)
+            | Instruction::NegLookBehind(min, max, addr) => {
+                if min > max {
+                    return Err(LoadError::InvalidOperand(pc));
+                }
+                check_target(pc, *addr)?;
+            }
+            Instruction::CounterReset(n) if *n >= resets => {
+                return Err(LoadError::InvalidOperand(pc));
+            }
+            Instruction::CounterCheck(n, min, max, addr) => {
+                if min > max || *n >= resets || !is_reset(*n) {
+                    return Err(LoadError::InvalidOperand(pc));
+                }
+                check_target(pc, *addr)?;
+            }
+            Instruction::CounterInc(n) if *n >= resets || !is_reset(*n) => {
+                return Err(LoadError::InvalidOperand(pc));
+            }
+            Instruction::Save(slot) if *slot >= 2 * (group_count + 1) => {
+                return Err(LoadError::InvalidOperand(pc));
+            }
+            Instruction::Backref(n) if *n > group_count => {
+                return Err(LoadError::InvalidOperand(pc));
+            }
+            _ => {}
+        }
+    }
+    match code.last() {
+        Some(Instruction::Match | Instruction::Jump(_)) => {}
+        _ => return Err(LoadError::FallsOffEnd(code.len() - 1)),
+    }
+    check_counters_reset(code, resets)?;
+    check_loops(code)
+}
+
+/// Returns the addresses that the instruction at `pc` may continue at. The
+/// sub-program of a lookaround or an atomic group and the address after it
+/// are both successors.
+fn successors(code: &[Instruction], pc: usize) -> Vec<usize> {
+    match &code[pc] {
+        Instruction::Match => vec![],
+        Instruction::Jump(addr) => vec![*addr],
+        Instruction::Split(addr1, addr2) => vec![*addr1, *addr2],
+        Instruction::CounterCheck(_, _, _, addr)
+        | Instruction::LookAhead(addr)
+        | Instruction::NegLookAhead(addr)
+        | Instruction::LookBehind(_, _, addr)
+        | Instruction::NegLookBehind(_, _, addr)
+        | Instruction::Atomic(addr) => vec![pc + 1, *addr],
+        _ => vec![pc + 1],
+    }
+}
+
+/// Checks that on every path from the start a counter is reset before it is
+/// incremented or checked. The counters reset on all paths to each address
+/// are computed as bit sets until they no longer change. Resets inside a
+/// sub-program are ignored after it, which only removes counters from the
+/// sets.
+fn check_counters_reset(code: &[Instruction], resets: usize) -> Result<(), LoadError> {
+    if resets == 0 {
+        return Ok(());
+    }
+    let words = resets.div_ceil(64);
+    let mut reset_in: Vec<Option<Vec<u64>>> = vec![None; code.len()];
+    reset_in[0] = Some(vec![0; words]);
+    let mut work = vec![0];
+    while let Some(pc) = work.pop() {
+        let mut reset = reset_in[pc].clone().unwrap_or_default();
+        if let Instruction::CounterReset(n) = &code[pc] {
+            reset[n / 64] |= 1 << (n % 64);
+        }
+        let sub_end = match &code[pc] {
+            Instruction::LookAhead(addr)
+            | Instruction::NegLookAhead(addr)
+            | Instruction::LookBehind(_, _, addr)
+            | Instruction::NegLookBehind(_, _, addr)
+            | Instruction::Atomic(addr) => Some(*addr),
+            _ => None,
+        };
+        for next in successors(code, pc) {
+            let incoming = if sub_end == Some(next) {
+                reset_in[pc].clone().unwrap_or_default()
+            } else {
+                reset.clone()
+            };
+            let merged = match &reset_in[next] {
+                Some(old) => old.iter().zip(&incoming).map(|(a, b)| a & b).collect(),
+                None => incoming,
+            };
+            if reset_in[next].as_ref() != Some(&merged) {
+                reset_in[next] = Some(merged);
+                work.push(next);
+            }
+        }
+    }
+
+    for (pc, (inst, reset)) in code.iter().zip(&reset_in).enumerate() {
+        let (Instruction::CounterInc(n) | Instruction::CounterCheck(n, ..)) = inst else {
+            continue;
+        };
+        if let Some(reset) = reset {
+            if reset[n / 64] & (1 << (n % 64)) == 0 {
+                return Err(LoadError::InvalidOperand(pc));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every loop has a `split` or a `counter_check`, which may leave
+/// it, or an instruction consuming a char, so that following the program
+/// without input always ends.
+fn check_loops(code: &[Instruction]) -> Result<(), LoadError> {
+    let is_plain = |pc: usize| {
+        !matches!(
+            code[pc],
+            Instruction::Split(..)
+                | Instruction::CounterCheck(..)
+                | Instruction::Char(_)
+                | Instruction::Class(_)
+                | Instruction::AnyChar
+                | Instruction::AnyGrapheme
+                | Instruction::Match
+        )
+    };
+    // 0: not visited, 1: on the current path, 2: done
+    let mut marks = vec![0u8; code.len()];
+    for start in 0..code.len() {
+        if marks[start] != 0 || !is_plain(start) {
+            continue;
+        }
+        marks[start] = 1;
+        let mut stack = vec![(start, successors(code, start))];
+        while let Some((pc, nexts)) = stack.last_mut() {
+            match nexts.pop() {
+                Some(next) if is_plain(next) => match marks[next] {
+                    0 => {
+                        marks[next] = 1;
+                        stack.push((next, successors(code, next)));
+                    }
+                    1 => return Err(LoadError::EmptyLoop(next)),
+                    _ => {}
+                },
+                Some(_) => {}
+                None => {
+                    marks[*pc] = 2;
+                    stack.pop();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// CRC-32 with the polynomial of zlib and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, LoadError, FORMAT_VERSION, HEADER_LEN};
+    use crate::engine::{Instruction, MatchKind, Regex, RegexBuilder};
+    use rstest::*;
+
+    fn disassemble(regex: &Regex) -> Vec<String> {
+        regex.code.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[rstest]
+    #[case("abc", &["xabcx", "ab"])]
+    #[case("^(a|b)*c$", &["ababc", "abx"])]
+    #[case("[^[:digit:]\u{3042}-\u{3093}]+x", &["zzx", "1x", "\u{3044}x"])]
+    #[case("(a+)\\1", &["aaaa", "ab"])]
+    #[case("a(?=b)|(?<!x)c", &["ab", "xc", "yc"])]
+    #[case("(?<=ab{1,2})c", &["abbc", "ac"])]
+    #[case("(?>a+)b", &["aab", "aa"])]
+    #[case("x[ab]{20,30}y", &["xaaaaaaaaaaaaaaaaaaaaay", "xaby"])]
+    #[case("(ab){20,}", &["ab", "abababababababababababababababababababab"])]
+    #[case("(?=(ab){1001})(?>(ab){2000}){1,2}c", &["abc", "xabc"])]
+    fn test_roundtrip(#[case] expr: &str, #[case] lines: &[&str]) {
+        let regex = Regex::new(expr).unwrap();
+        let loaded = Regex::from_bytes(&regex.to_bytes()).unwrap();
+        assert_eq!(disassemble(&loaded), disassemble(&regex));
+        assert_eq!(loaded.group_count, regex.group_count);
+        for line in lines {
+            for is_depth in [true, false] {
+                if let Ok(expected) = regex.is_match(line, is_depth) {
+                    assert_eq!(loaded.is_match(line, is_depth).unwrap(), expected);
+                }
+            }
+            assert_eq!(
+                loaded.captures(line, true).unwrap(),
+                regex.captures(line, true).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_match_kind() {
+        let regex = RegexBuilder::new("a|ab")
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        let loaded = Regex::from_bytes(&regex.to_bytes()).unwrap();
+        assert_eq!(loaded.match_kind, MatchKind::LeftmostLongest);
+        assert_eq!(loaded.captures("ab", true).unwrap(), Some(vec![Some(0..2)]));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let bytes = Regex::new("a(b|c)*d").unwrap().to_bytes();
+
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            Regex::from_bytes(&flipped).unwrap_err(),
+            LoadError::ChecksumMismatch
+        );
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert_eq!(Regex::from_bytes(&magic).unwrap_err(), LoadError::BadMagic);
+
+        let mut version = bytes.clone();
+        version[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Regex::from_bytes(&version).unwrap_err(),
+            LoadError::UnsupportedVersion(FORMAT_VERSION + 1)
+        );
+
+        let mut flags = bytes.clone();
+        flags[6] = 0x80;
+        assert_eq!(
+            Regex::from_bytes(&flags).unwrap_err(),
+            LoadError::UnknownFlags(0x80)
+        );
+
+        for len in [0, 3, HEADER_LEN - 1, bytes.len() - 1] {
+            assert_eq!(
+                Regex::from_bytes(&bytes[..len]).unwrap_err(),
+                LoadError::Truncated
+            );
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Regex::from_bytes(&trailing).unwrap_err(),
+            LoadError::TrailingBytes
+        );
+    }
+
+    #[rstest]
+    #[case(vec![Instruction::Jump(2), Instruction::Match], 0, LoadError::InvalidTarget { pc: 0, target: 2 })]
+    #[case(vec![Instruction::Split(1, 7), Instruction::Match], 0, LoadError::InvalidTarget { pc: 0, target: 7 })]
+    #[case(vec![Instruction::Char('a'), Instruction::LookAhead(9), Instruction::Match], 0, LoadError::InvalidTarget { pc: 1, target: 9 })]
+    #[case(vec![Instruction::CounterReset(0), Instruction::CounterCheck(0, 1, 2, 4), Instruction::Match], 0, LoadError::InvalidTarget { pc: 1, target: 4 })]
+    #[case(vec![Instruction::Match, Instruction::Char('a')], 0, LoadError::FallsOffEnd(1))]
+    #[case(vec![], 0, LoadError::FallsOffEnd(0))]
+    #[case(vec![Instruction::Save(4), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::Backref(1), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::LookBehind(2, 1, 1), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::CounterInc(0), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::CounterReset(1 << 40), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::CounterReset(0), Instruction::CounterInc(1 << 40), Instruction::Match], 0, LoadError::InvalidOperand(1))]
+    #[case(vec![Instruction::Save(0), Instruction::Save(1), Instruction::Match], 1 << 50, LoadError::InvalidGroupCount(1 << 50))]
+    #[case(vec![Instruction::Save(1 << 40), Instruction::Match], 1 << 39, LoadError::InvalidGroupCount(1 << 39))]
+    #[case(vec![Instruction::CounterInc(0), Instruction::CounterReset(0), Instruction::Match], 0, LoadError::InvalidOperand(0))]
+    #[case(vec![Instruction::Split(1, 2), Instruction::CounterReset(0), Instruction::CounterCheck(0, 1, 2, 3), Instruction::Match], 0, LoadError::InvalidOperand(2))]
+    #[case(vec![Instruction::Jump(0), Instruction::Match], 0, LoadError::EmptyLoop(0))]
+    #[case(vec![Instruction::Save(0), Instruction::Atomic(3), Instruction::Jump(0), Instruction::Save(1), Instruction::Match], 0, LoadError::EmptyLoop(0))]
+    fn test_invalid_program(
+        #[case] code: Vec<Instruction>,
+        #[case] group_count: usize,
+        #[case] expected: LoadError,
+    ) {
+        let regex = Regex {
+            code,
+            group_count,
+            match_kind: MatchKind::default(),
+            bit_parallel: None,
+        };
+        assert_eq!(Regex::from_bytes(&regex.to_bytes()).unwrap_err(), expected);
+    }
+
+    #[test]
+    fn test_invalid_char() {
+        let mut bytes = Regex::new("a").unwrap().to_bytes();
+        // the group count and the instruction count precede the tag of `char`
+        let pos = HEADER_LEN + 17;
+        assert_eq!(bytes[pos - 1], 0);
+        bytes[pos..pos + 4].copy_from_slice(&0xD800u32.to_le_bytes());
+        let checksum = crc32(&bytes[HEADER_LEN..]);
+        bytes[12..16].copy_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            Regex::from_bytes(&bytes).unwrap_err(),
+            LoadError::InvalidOperand(0)
+        );
+    }
+}
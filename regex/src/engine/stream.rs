@@ -0,0 +1,444 @@
+//! Search over input that arrives in chunks, such as a socket or a large
+//! file, without holding all of it in memory. The threads of the
+//! breadth-first evaluator are carried from one chunk to the next, and only
+//! the input after a match that may still be extended is kept.
+
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    io::{self, Read},
+    ops::Range,
+};
+
+use super::{
+    codegen::Instruction,
+    evaluator::{EvalError, Haystack, Lockstep, RegisterContext, Scratch},
+    MatchKind, Regex,
+};
+
+/// size of the chunks read by `StreamMatches`
+const CHUNK_SIZE: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub enum StreamError {
+    Io(io::Error),
+    /// the input is not valid UTF-8 at this byte offset
+    InvalidUtf8(usize),
+    /// the instruction reads the input around the current char, which is not
+    /// kept, as backreferences, lookaround, atomic groups and grapheme
+    /// clusters do
+    Unsupported(Instruction),
+    Eval(EvalError),
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamError::Io(e) => write!(f, "StreamError: {e}"),
+            StreamError::InvalidUtf8(pos) => {
+                write!(f, "StreamError: invalid UTF-8: pos = {pos}")
+            }
+            StreamError::Unsupported(inst) => {
+                write!(
+                    f,
+                    "StreamError: `{inst}` is unsupported in streaming search"
+                )
+            }
+            StreamError::Eval(e) => write!(f, "StreamError: {e}"),
+        }
+    }
+}
+
+impl Error for StreamError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            StreamError::Io(e) => Some(e),
+            StreamError::Eval(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StreamError {
+    fn from(e: io::Error) -> Self {
+        StreamError::Io(e)
+    }
+}
+
+impl From<EvalError> for StreamError {
+    fn from(e: EvalError) -> Self {
+        StreamError::Eval(e)
+    }
+}
+
+/// Byte range in the whole input of each match followed by the range of each
+/// capture group, as returned by `Regex::captures`.
+pub type StreamCaptures = Vec<Option<Range<usize>>>;
+
+/// The char evaluated by the threads of a `StreamSearcher`, whose string
+/// pointers are byte offsets in the whole input.
+struct StreamInput {
+    pos: usize,
+    /// `None` at the end of the input
+    next: Option<char>,
+}
+
+impl Haystack for StreamInput {
+    fn next_char(&self, sp: usize) -> Option<(char, usize)> {
+        self.next
+            .filter(|_| sp == self.pos)
+            .map(|c| (c, sp + c.len_utf8()))
+    }
+
+    fn is_end(&self, sp: usize) -> bool {
+        self.next.is_none() && sp == self.pos
+    }
+
+    fn line(&self) -> Option<&[char]> {
+        None
+    }
+}
+
+/// Finds the successive non-overlapping matches in input fed chunk by chunk.
+pub struct StreamSearcher<'a> {
+    code: &'a [Instruction],
+    group_count: usize,
+    kind: MatchKind,
+    /// trailing bytes of a char split between chunks
+    partial: Vec<u8>,
+    /// byte offset of the first byte of `partial`
+    offset: usize,
+    /// decoded chars not evaluated yet with their byte offsets
+    input: VecDeque<(usize, char)>,
+    /// chars evaluated since the end of `matched`, evaluated again from that
+    /// end once the match is final
+    retained: VecDeque<(usize, char)>,
+    /// byte offset of the char evaluated next
+    pos: usize,
+    /// threads waiting for the char at `pos`, with the byte offset of their
+    /// start in capture slot 0
+    lockstep: Lockstep,
+    scratch: Scratch,
+    /// the preferred match found so far, which longer running threads may
+    /// still replace
+    matched: Option<RegisterContext>,
+    /// an empty match ended here, so no other may start at this position
+    empty_end: Option<usize>,
+}
+
+impl<'a> StreamSearcher<'a> {
+    pub fn new(regex: &'a Regex) -> Result<StreamSearcher<'a>, StreamError> {
+        if let Some(i) = regex.code.iter().find(|i| {
+            matches!(
+                i,
+                Instruction::Backref(_)
+                    | Instruction::LookAhead(_)
+                    | Instruction::NegLookAhead(_)
+                    | Instruction::LookBehind(..)
+                    | Instruction::NegLookBehind(..)
+                    | Instruction::Atomic(_)
+                    | Instruction::AnyGrapheme
+            )
+        }) {
+            return Err(StreamError::Unsupported(i.clone()));
+        }
+
+        let has_counters = regex
+            .code
+            .iter()
+            .any(|i| matches!(i, Instruction::CounterReset(_)));
+        let mut lockstep = Lockstep::default();
+        lockstep.reset(&regex.code, has_counters);
+        Ok(StreamSearcher {
+            code: &regex.code,
+            group_count: regex.group_count,
+            kind: regex.match_kind,
+            partial: Vec::new(),
+            offset: 0,
+            input: VecDeque::new(),
+            retained: VecDeque::new(),
+            pos: 0,
+            lockstep,
+            scratch: Scratch::default(),
+            matched: None,
+            empty_end: None,
+        })
+    }
+
+    /// Consumes the next chunk of the input and returns the matches that no
+    /// later input can change.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<StreamCaptures>, StreamError> {
+        self.partial.extend_from_slice(chunk);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(s) => s.len(),
+            // a char continues in the next chunk
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(StreamError::InvalidUtf8(self.offset + e.valid_up_to())),
+        };
+        let rest = self.partial.split_off(valid);
+        let decoded = std::str::from_utf8(&self.partial).unwrap_or_default();
+        let offset = self.offset;
+        self.input
+            .extend(decoded.char_indices().map(|(i, c)| (offset + i, c)));
+        self.offset += valid;
+        self.partial = rest;
+
+        let mut found = Vec::new();
+        while let Some((pos, c)) = self.input.pop_front() {
+            self.step(Some((pos, c)), &mut found)?;
+        }
+        Ok(found)
+    }
+
+    /// Ends the input and returns the remaining matches.
+    pub fn finish(mut self) -> Result<Vec<StreamCaptures>, StreamError> {
+        if !self.partial.is_empty() {
+            return Err(StreamError::InvalidUtf8(self.offset));
+        }
+
+        let mut found = Vec::new();
+        loop {
+            while let Some((pos, c)) = self.input.pop_front() {
+                self.step(Some((pos, c)), &mut found)?;
+            }
+            // a match reported at the end puts back the input after it, and
+            // another match may follow
+            let count = found.len();
+            self.step(None, &mut found)?;
+            if found.len() == count {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Advances all threads over the char at `self.pos`, or over the end of
+    /// the input if `next` is `None`.
+    fn step(
+        &mut self,
+        next: Option<(usize, char)>,
+        found: &mut Vec<StreamCaptures>,
+    ) -> Result<(), StreamError> {
+        if self.matched.is_none() && self.empty_end != Some(self.pos) {
+            // a new start has the lowest priority
+            self.lockstep.push(RegisterContext {
+                pc: 0,
+                sp: self.pos,
+                caps: vec![Some(self.pos)],
+                counters: Vec::new(),
+            });
+        }
+
+        let input = StreamInput {
+            pos: self.pos,
+            next: next.map(|(_, c)| c),
+        };
+        let is_matched = self.lockstep.step(
+            self.code,
+            &input,
+            self.pos,
+            self.kind,
+            &mut self.matched,
+            &mut self.scratch,
+        )?;
+        if is_matched {
+            // the threads started after the match cannot replace it
+            let start = self.matched.as_ref().and_then(|m| m.caps[0]);
+            self.lockstep.retain(|thread| thread.caps[0] <= start);
+            self.retained.clear();
+        }
+
+        if let Some((pos, c)) = next {
+            if self.matched.is_some() {
+                self.retained.push_back((pos, c));
+            }
+            self.pos = pos + c.len_utf8();
+        }
+
+        if self.lockstep.is_empty() {
+            if let Some(matched) = self.matched.take() {
+                self.emit(matched, found);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports a final match and restarts the search at its end.
+    fn emit(&mut self, matched: RegisterContext, found: &mut Vec<StreamCaptures>) {
+        let mut caps = matched.caps;
+        caps.resize(caps.len().max(2), None);
+        caps[1] = Some(matched.sp);
+        let (start, end) = (caps[0], caps[1]);
+        found.push(
+            (0..=self.group_count)
+                .map(|n| match (caps.get(2 * n), caps.get(2 * n + 1)) {
+                    (Some(Some(start)), Some(Some(end))) if start <= end => Some(*start..*end),
+                    _ => None,
+                })
+                .collect(),
+        );
+
+        if let Some(end) = end {
+            self.pos = end;
+            self.empty_end = (start == Some(end)).then_some(end);
+        }
+        while let Some(retained) = self.retained.pop_back() {
+            self.input.push_front(retained);
+        }
+    }
+}
+
+/// Iterator over the matches in the input read from a reader.
+pub struct StreamMatches<'a, R> {
+    searcher: Option<StreamSearcher<'a>>,
+    reader: R,
+    found: VecDeque<StreamCaptures>,
+    buf: Vec<u8>,
+}
+
+impl<'a, R: Read> StreamMatches<'a, R> {
+    pub fn new(regex: &'a Regex, reader: R) -> Result<StreamMatches<'a, R>, StreamError> {
+        Ok(StreamMatches {
+            searcher: Some(StreamSearcher::new(regex)?),
+            reader,
+            found: VecDeque::new(),
+            buf: vec![0; CHUNK_SIZE],
+        })
+    }
+
+    fn fill(&mut self) -> Result<(), StreamError> {
+        while self.found.is_empty() {
+            let searcher = match self.searcher.as_mut() {
+                Some(searcher) => searcher,
+                None => return Ok(()),
+            };
+            let n = match self.reader.read(&mut self.buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let found = if n == 0 {
+                self.searcher
+                    .take()
+                    .map_or(Ok(Vec::new()), |s| s.finish())?
+            } else {
+                searcher.feed(&self.buf[..n])?
+            };
+            self.found.extend(found);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: Read> Iterator for StreamMatches<'a, R> {
+    type Item = Result<StreamCaptures, StreamError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.fill() {
+            // no further match is reported after an error
+            self.searcher = None;
+            self.found.clear();
+            return Some(Err(e));
+        }
+        self.found.pop_front().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamError, StreamMatches, StreamSearcher};
+    use crate::engine::{MatchKind, Regex, RegexBuilder};
+    use rstest::*;
+
+    fn search_chunked(regex: &Regex, input: &str, size: usize) -> Vec<std::ops::Range<usize>> {
+        let mut searcher = StreamSearcher::new(regex).unwrap();
+        let mut found = Vec::new();
+        for chunk in input.as_bytes().chunks(size) {
+            found.extend(searcher.feed(chunk).unwrap());
+        }
+        found.extend(searcher.finish().unwrap());
+        found
+            .into_iter()
+            .map(|caps| caps[0].clone().unwrap())
+            .collect()
+    }
+
+    #[rstest]
+    #[case("ab", "xabyab", vec![1..3, 4..6])]
+    #[case("a+", "baaca", vec![1..3, 4..5])]
+    #[case("a*", "baa", vec![0..0, 1..3, 3..3])]
+    #[case("^a", "aaa", vec![0..1])]
+    #[case("a$", "aaa", vec![2..3])]
+    #[case("(ab)+c", "abababx ababc", vec![8..13])]
+    #[case("あ.う", "ああいうえ", vec![3..12])]
+    #[case("[^x]+", "xxéaxb", vec![2..5, 6..7])]
+    #[case("ab|abcd", "abcd", vec![0..2])]
+    #[case("a{20}", &format!("x{}x", "a".repeat(41)), vec![1..21, 21..41])]
+    fn test_chunks(
+        #[case] expr: &str,
+        #[case] input: &str,
+        #[case] expected: Vec<std::ops::Range<usize>>,
+    ) {
+        let regex = Regex::new(expr).unwrap();
+        for size in 1..=input.len() {
+            assert_eq!(search_chunked(&regex, input, size), expected, "{size}");
+        }
+    }
+
+    #[test]
+    fn test_counter() {
+        let regex = Regex::new("xa{1001}b?").unwrap();
+        let input = format!("xx{}bxa{}", "a".repeat(1001), "a".repeat(1001));
+        for size in [1, 7, 1000, input.len()] {
+            assert_eq!(search_chunked(&regex, &input, size), vec![1..1004, 1004..2006]);
+        }
+    }
+
+    #[test]
+    fn test_longest() {
+        let regex = RegexBuilder::new("ab|abcd|x(y|yz)")
+            .match_kind(MatchKind::LeftmostLongest)
+            .build()
+            .unwrap();
+        for size in 1..=8 {
+            assert_eq!(search_chunked(&regex, "abcdxyz", size), vec![0..4, 4..7]);
+        }
+    }
+
+    #[test]
+    fn test_reader() {
+        let regex = Regex::new("(a)(b)?").unwrap();
+        let input = "ab a".repeat(5_000);
+        let matches = StreamMatches::new(&regex, input.as_bytes())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(matches.len(), 10_000);
+        assert_eq!(matches[1], vec![Some(3..4), Some(3..4), None]);
+        assert_eq!(matches[9_999][0], Some(19_999..20_000));
+    }
+
+    #[test]
+    fn test_errors() {
+        let regex = Regex::new("a(?=b)").unwrap();
+        assert!(matches!(
+            StreamSearcher::new(&regex),
+            Err(StreamError::Unsupported(_))
+        ));
+
+        let regex = Regex::new("a").unwrap();
+        let mut searcher = StreamSearcher::new(&regex).unwrap();
+        assert!(searcher.feed(&[b'a', 0xE3, 0x81]).is_ok());
+        assert!(matches!(
+            searcher.finish(),
+            Err(StreamError::InvalidUtf8(1))
+        ));
+
+        let mut searcher = StreamSearcher::new(&regex).unwrap();
+        assert!(matches!(
+            searcher.feed(&[b'a', 0xFF]),
+            Err(StreamError::InvalidUtf8(1))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+//! Unicode properties for `\p{..}` and extended grapheme clusters for `.` in
+//! grapheme mode, following UAX #29.
+
+#[rustfmt::skip]
+mod tables;
+
+use super::class::CharClass;
+
+pub use tables::UNICODE_VERSION;
+
+/// sorted, non-overlapping ranges of a property value
+type Ranges = &'static [(char, char)];
+
+/// Grapheme cluster break property of a char.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphemeBreak {
+    Cr,
+    Lf,
+    Control,
+    Extend,
+    Zwj,
+    RegionalIndicator,
+    Prepend,
+    SpacingMark,
+    L,
+    V,
+    T,
+    Lv,
+    Lvt,
+    Other,
+}
+
+/// long names of the general categories made of those sharing the first
+/// letter
+const CATEGORY_GROUPS: &[(&str, &str)] = &[
+    ("L", "Letter"),
+    ("M", "Mark"),
+    ("N", "Number"),
+    ("P", "Punctuation"),
+    ("S", "Symbol"),
+    ("Z", "Separator"),
+    ("C", "Other"),
+];
+
+/// Returns the class named in `\p{name}`. The name is a general category
+/// such as `L`, `Lu` or `Uppercase_Letter`, or a script such as `Greek` or
+/// `Grek`, optionally prefixed with `gc=` or `sc=`. Names are compared
+/// ignoring case, spaces, `_` and `-`.
+pub fn property_class(name: &str) -> Option<CharClass> {
+    let (key, value) = match name.split_once(['=', ':']) {
+        Some((key, value)) => (Some(loose(key)), loose(value)),
+        None => (None, loose(name)),
+    };
+    let is_category = matches!(key.as_deref(), None | Some("gc" | "generalcategory"));
+    let is_script = matches!(key.as_deref(), None | Some("sc" | "script"));
+
+    if is_category {
+        if let Some((short, _)) = CATEGORY_GROUPS
+            .iter()
+            .find(|(short, long)| loose(short) == value || loose(long) == value)
+        {
+            let ranges = tables::GENERAL_CATEGORY
+                .iter()
+                .filter(|(name, _, _)| name.starts_with(short) && *name != "LC")
+                .flat_map(|(_, _, ranges)| ranges.iter().copied())
+                .collect();
+            return Some(CharClass::new(ranges));
+        }
+        if let Some(ranges) = find(tables::GENERAL_CATEGORY, &value) {
+            return Some(CharClass::new(ranges.to_vec()));
+        }
+    }
+    if is_script {
+        if let Some(ranges) = find(tables::SCRIPT, &value) {
+            return Some(CharClass::new(ranges.to_vec()));
+        }
+    }
+    None
+}
+
+fn find(table: &[(&str, &str, Ranges)], value: &str) -> Option<Ranges> {
+    table
+        .iter()
+        .find(|(short, long, _)| loose(short) == value || loose(long) == value)
+        .map(|(_, _, ranges)| *ranges)
+}
+
+fn loose(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, ' ' | '_' | '-'))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn grapheme_break(c: char) -> GraphemeBreak {
+    let table = tables::GRAPHEME_BREAK;
+    let i = table.partition_point(|(_, hi, _)| *hi < c);
+    match table.get(i) {
+        Some((lo, _, property)) if *lo <= c => *property,
+        _ => GraphemeBreak::Other,
+    }
+}
+
+fn is_extended_pictographic(c: char) -> bool {
+    let table = tables::EXTENDED_PICTOGRAPHIC;
+    let i = table.partition_point(|(_, hi)| *hi < c);
+    matches!(table.get(i), Some((lo, _)) if *lo <= c)
+}
+
+/// Returns the number of chars in the extended grapheme cluster starting at
+/// `line[start]`, or 0 at the end of the line.
+pub fn grapheme_len(line: &[char], start: usize) -> usize {
+    if start >= line.len() {
+        return 0;
+    }
+    let mut end = start + 1;
+    while end < line.len() && !is_boundary(&line[start..], end - start) {
+        end += 1;
+    }
+    end - start
+}
+
+/// Returns `true` if a cluster starting at `cluster[0]` breaks before
+/// `cluster[i]`.
+fn is_boundary(cluster: &[char], i: usize) -> bool {
+    use GraphemeBreak::*;
+
+    let (before, after) = (grapheme_break(cluster[i - 1]), grapheme_break(cluster[i]));
+    match (before, after) {
+        // GB3, GB4, GB5
+        (Cr, Lf) => false,
+        (Cr | Lf | Control, _) | (_, Cr | Lf | Control) => true,
+        // GB6, GB7, GB8
+        (L, L | V | Lv | Lvt) | (Lv | V, V | T) | (Lvt | T, T) => false,
+        // GB9, GB9a, GB9b
+        (_, Extend | Zwj | SpacingMark) | (Prepend, _) => false,
+        // GB11
+        (Zwj, _) if is_extended_pictographic(cluster[i]) => !cluster[..i - 1]
+            .iter()
+            .rev()
+            .find(|c| grapheme_break(**c) != Extend)
+            .is_some_and(|c| is_extended_pictographic(*c)),
+        // GB12, GB13
+        (RegionalIndicator, RegionalIndicator) => {
+            let count = cluster[..i]
+                .iter()
+                .rev()
+                .take_while(|c| grapheme_break(**c) == RegionalIndicator)
+                .count();
+            count % 2 == 0
+        }
+        // GB999
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grapheme_len, property_class};
+    use rstest::*;
+
+    #[rstest]
+    #[case("L", 'é', true)]
+    #[case("L", '1', false)]
+    #[case("Letter", 'あ', true)]
+    #[case("Lu", 'Ω', true)]
+    #[case("lowercase letter", 'Ω', false)]
+    #[case("Nd", '٣', true)]
+    #[case("gc=P", '!', true)]
+    #[case("Greek", 'ω', true)]
+    #[case("sc=Grek", 'w', false)]
+    #[case("Script=Han", '漢', true)]
+    #[case("Hiragana", 'ア', false)]
+    #[case("Zs", '\u{3000}', true)]
+    fn test_property(#[case] name: &str, #[case] c: char, #[case] expected: bool) {
+        assert_eq!(property_class(name).unwrap().contains(c), expected);
+    }
+
+    #[rstest]
+    #[case("Klingon")]
+    #[case("sc=Lu")]
+    #[case("gc=Greek")]
+    fn test_unknown_property(#[case] name: &str) {
+        assert!(property_class(name).is_none());
+    }
+
+    #[rstest]
+    #[case("\r\na", vec![2, 1])]
+    #[case("e\u{301}x", vec![2, 1])]
+    #[case("⛹\u{200d}♂a", vec![3, 1])]
+    #[case("👩\u{1f3fd}\u{200d}💻", vec![4])]
+    #[case("a\u{200d}♂", vec![2, 1])]
+    #[case("🇯🇵🇫", vec![2, 1])]
+    #[case("각ᄀ\u{1161}", vec![1, 2])]
+    #[case("\u{600}1", vec![2])]
+    fn test_grapheme(#[case] line: &str, #[case] expected: Vec<usize>) {
+        let line = line.chars().collect::<Vec<char>>();
+        let mut lens = Vec::new();
+        let mut start = 0;
+        while start < line.len() {
+            let len = grapheme_len(&line, start);
+            lens.push(len);
+            start += len;
+        }
+        assert_eq!(lens, expected);
+    }
+}
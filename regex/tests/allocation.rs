@@ -0,0 +1,76 @@
+//! Counts the heap allocations of matches with a reused `Cache`. The counting
+//! allocator replaces the global one, so it lives in its own test binary.
+
+use regex::engine::{Cache, Engine, Regex};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+/// Counts the allocations of the current thread, so that the tests running
+/// in parallel do not disturb each other.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Returns the allocations made by 10 matches once the cache is warm.
+fn allocations(regex: &Regex, line: &str, engine: Engine, cache: &mut Cache) -> usize {
+    regex.is_match_engine(line, engine, cache).unwrap();
+    let before = ALLOCATIONS.with(|n| n.get());
+    for _ in 0..10 {
+        regex.is_match_engine(line, engine, cache).unwrap();
+    }
+    ALLOCATIONS.with(|n| n.get()) - before
+}
+
+#[test]
+fn test_no_allocation() {
+    for (expr, line) in [
+        ("a(b|c)*d$", "abcbcbcbcbcbcbd"),
+        ("(a|a?)+b", "aaaaaaaaaaaaaaaa"),
+        ("^[[:alpha:]]{2,4}\\.[a-z]+", "xy.rs"),
+    ] {
+        let regex = Regex::new(expr).unwrap();
+        let mut cache = Cache::new();
+        for engine in [
+            Engine::DepthFirst,
+            Engine::BreadthFirst,
+            Engine::BitParallel,
+        ] {
+            assert_eq!(allocations(&regex, line, engine, &mut cache), 0, "{expr}");
+        }
+    }
+}
+
+#[test]
+fn test_no_allocation_evaluator() {
+    // the general evaluator keeps its queues and visited sets in the cache,
+    // and the registers stay empty without captures or counters
+    for (expr, line) in [
+        ("a(?=b)b.*c", "xxaabxxxc"),
+        ("(?<!x)a+b", "xaaab"),
+        ("^(?=.*1)(?=.*a).+$", "x1b"),
+    ] {
+        let regex = Regex::new(expr).unwrap();
+        let mut cache = Cache::new();
+        for engine in [Engine::DepthFirst, Engine::BreadthFirst] {
+            assert_eq!(allocations(&regex, line, engine, &mut cache), 0, "{expr}");
+        }
+    }
+}
@@ -0,0 +1,82 @@
+/* Exercises the C interface; run by tests/capi.rs. */
+#include <stdio.h>
+#include <string.h>
+
+#include "rz_regex.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                            \
+    do {                                                                       \
+        if (!(cond)) {                                                         \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,  \
+                    #cond);                                                    \
+            failures++;                                                        \
+        }                                                                      \
+    } while (0)
+
+static void test_match(void) {
+    rz_error *error = NULL;
+    rz_regex *regex = rz_regex_new("(a+)(x)?b$", &error);
+    CHECK(regex != NULL && error == NULL);
+
+    const char *subject = "xx\xe3\x81\x82" "aab";
+    size_t len = strlen(subject);
+    CHECK(rz_regex_is_match(regex, subject, len, &error) == 1);
+    CHECK(rz_regex_is_match(regex, "ba", 2, &error) == 0);
+    CHECK(rz_regex_is_match(regex, NULL, 0, &error) == 0);
+
+    size_t start = 0, end = 0;
+    CHECK(rz_regex_find(regex, subject, len, &start, &end, &error) == 1);
+    CHECK(start == 5 && end == 8);
+
+    rz_captures *captures = NULL;
+    CHECK(rz_regex_captures(regex, subject, len, &captures, &error) == 1);
+    CHECK(rz_captures_len(captures) == 3);
+    CHECK(rz_captures_get(captures, 1, &start, &end) == 1);
+    CHECK(start == 5 && end == 7);
+    CHECK(rz_captures_get(captures, 2, &start, &end) == 0);
+    CHECK(rz_captures_get(captures, 9, NULL, NULL) == 0);
+    rz_captures_free(captures);
+
+    CHECK(error == NULL);
+    rz_regex_free(regex);
+}
+
+static void test_backref(void) {
+    rz_regex *regex = rz_regex_new("(ab|cd)\\1", NULL);
+    CHECK(regex != NULL);
+    CHECK(rz_regex_is_match(regex, "xcdcd", 5, NULL) == 1);
+    CHECK(rz_regex_is_match(regex, "xabcd", 5, NULL) == 0);
+    rz_regex_free(regex);
+}
+
+static void test_errors(void) {
+    rz_error *error = NULL;
+    CHECK(rz_regex_new("(ab", &error) == NULL);
+    CHECK(error != NULL && strlen(rz_error_message(error)) > 0);
+    rz_error_free(error);
+
+    error = NULL;
+    rz_regex *regex = rz_regex_new("a", NULL);
+    CHECK(rz_regex_find(regex, "\xff", 1, NULL, NULL, &error) == -1);
+    CHECK(error != NULL &&
+          strcmp(rz_error_message(error), "subject is not valid UTF-8") == 0);
+    rz_error_free(error);
+    rz_regex_free(regex);
+
+    rz_regex_free(NULL);
+    rz_captures_free(NULL);
+    rz_error_free(NULL);
+}
+
+int main(void) {
+    test_match();
+    test_backref();
+    test_errors();
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    return 0;
+}
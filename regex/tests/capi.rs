@@ -0,0 +1,31 @@
+//! Compiles `tests/c/capi_test.c` against the static library with the system C
+//! compiler and runs it.
+
+use std::{env, path::PathBuf, process::Command};
+
+#[test]
+fn test_c_program() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // the test binary is built in the same `deps` directory as the library
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let exe = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("capi_test");
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(root.join("tests/c/capi_test.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(deps.join("libregex.a"))
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&exe)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "failed to compile the C test");
+
+    let output = Command::new(&exe).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}